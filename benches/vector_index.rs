@@ -0,0 +1,110 @@
+//! Compares the vector indexes of the memory database on random embeddings.
+//!
+//! Run with `cargo bench --bench vector_index -- [sizes...]`, by default on
+//! 10k and 100k vectors. Like sentence embeddings, the vectors are clustered
+//! by topic; uniformly random vectors are much harder for approximate indexes.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use project_lily::mem_db::{BruteForceIndex, DistanceMetric, VectorIndex, VectorIndexType};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The dimension of the sentence embeddings used by the vector memory.
+const DIMENSION: usize = 384;
+const QUERIES: usize = 100;
+const COUNT: usize = 10;
+const TOPICS: usize = 100;
+
+fn random_vector(rng: &mut StdRng, scale: f32) -> Vec<f32> {
+    (0 .. DIMENSION)
+        .map(|_| rng.gen_range(-scale .. scale))
+        .collect()
+}
+
+/// A vector near one of the topics.
+fn embedding(rng: &mut StdRng, topics: &[Vec<f32>]) -> Vec<f32> {
+    let topic = &topics[rng.gen_range(0 .. topics.len())];
+    let noise = random_vector(rng, 0.5);
+    topic.iter().zip(noise).map(|(t, n)| t + n).collect()
+}
+
+fn main() {
+    let sizes = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse::<usize>().ok())
+        .collect::<Vec<_>>();
+    let sizes = match sizes.is_empty() {
+        true => vec![10_000, 100_000],
+        false => sizes,
+    };
+
+    println!(
+        "{:>8} {:>12} {:>12} {:>14} {:>10}",
+        "size", "index", "build", "query", "recall@10"
+    );
+
+    for size in sizes {
+        let mut rng = StdRng::seed_from_u64(size as u64);
+        let topics = (0 .. TOPICS)
+            .map(|_| random_vector(&mut rng, 1.0))
+            .collect::<Vec<_>>();
+        let vectors = (0 .. size)
+            .map(|_| embedding(&mut rng, &topics))
+            .collect::<Vec<_>>();
+        let queries = (0 .. QUERIES)
+            .map(|_| embedding(&mut rng, &topics))
+            .collect::<Vec<_>>();
+
+        let mut exact = BruteForceIndex::new(DIMENSION, DistanceMetric::Cosine);
+        for (id, vector) in vectors.iter().enumerate() {
+            exact.insert(id, vector).unwrap();
+        }
+        let expected = queries
+            .iter()
+            .map(|query| {
+                exact
+                    .search(query, COUNT, &|_| true)
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .collect::<HashSet<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for index_type in [
+            VectorIndexType::BruteForce,
+            VectorIndexType::KdTree,
+            VectorIndexType::Hnsw,
+        ] {
+            let start = Instant::now();
+            let mut index = index_type.build(DIMENSION, DistanceMetric::Cosine).unwrap();
+            for (id, vector) in vectors.iter().enumerate() {
+                index.insert(id, vector).unwrap();
+            }
+            let build = start.elapsed();
+
+            let mut query_time = Duration::ZERO;
+            let mut found = 0;
+            for (query, expected) in queries.iter().zip(&expected) {
+                let start = Instant::now();
+                let results = index.search(query, COUNT, &|_| true);
+                query_time += start.elapsed();
+
+                found += results
+                    .iter()
+                    .filter(|(id, _)| expected.contains(id))
+                    .count();
+            }
+
+            println!(
+                "{:>8} {:>12} {:>11.2}s {:>12.3}ms {:>10.3}",
+                size,
+                format!("{:?}", index_type),
+                build.as_secs_f64(),
+                query_time.as_secs_f64() * 1000.0 / QUERIES as f64,
+                found as f64 / (QUERIES * COUNT) as f64,
+            );
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::fmt;
 
-use itertools::Itertools;
+use crate::grammar::{CharClass, Expr, Grammar};
+use crate::llm::{ChatResponse, TokenProbs};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MessageAction {
@@ -65,7 +66,12 @@ impl MessageAction {
     pub fn as_grammar(&self) -> String {
         match self {
             MessageAction::Query { answers, .. } => answers.as_grammar(),
-            _ => String::from(r#"root ::= [^ \t\n] [^\t\n]* "\n""#),
+            _ => Grammar::new(Expr::sequence([
+                CharClass::none_of(" \t\n").into(),
+                Expr::from(CharClass::none_of("\t\n")).zero_or_more(),
+                Expr::literal("\n"),
+            ]))
+            .to_string(),
         }
     }
 
@@ -96,7 +102,7 @@ impl MessageAction {
                 "Analyze your emotional response, as well as ALL PAST emotional responses and emotional states to identify your current emotional state."
             }
             MessageAction::Command => {
-                "When in this state, you may send a command, using natural language, to the interpreter, which will then be executed if possible."
+                "When in this state, you may send one of the commands listed below to the interpreter, which will then be executed if possible."
             }
             MessageAction::Say => {
                 "When in this state, you may say something, using natural language, to the user. This is the ONLY state where you may directly communicate with the user."
@@ -121,14 +127,186 @@ pub enum QueryAnswers {
 
 impl QueryAnswers {
     pub fn as_grammar(&self) -> String {
+        let answer = match self {
+            QueryAnswers::Literals(answers) => Expr::one_of(answers.iter().cloned()),
+            QueryAnswers::String => Expr::from(CharClass::none_of("\n")).one_or_more(),
+            QueryAnswers::Boolean => Expr::one_of(["Yes", "No"]),
+            QueryAnswers::Number => Expr::from(CharClass::default().range('0', '9')).one_or_more(),
+        };
+
+        Grammar::new(Expr::sequence([answer, Expr::literal("\n")])).to_string()
+    }
+
+    /// The answers the grammar allows, if there is a fixed set of them.
+    pub fn choices(&self) -> Option<Vec<&str>> {
         match self {
-            QueryAnswers::Literals(answers) => {
-                let answer = answers.iter().map(|s| format!("\"{}\"", s)).join(" | ");
-                format!(r#"root ::= ({}) "\n""#, answer)
+            QueryAnswers::Literals(answers) => Some(answers.iter().map(String::as_str).collect()),
+            QueryAnswers::Boolean => Some(vec!["Yes", "No"]),
+            QueryAnswers::String | QueryAnswers::Number => None,
+        }
+    }
+
+    /// Estimates how likely the model was to give each of the allowed
+    /// answers, from the token probabilities of its response. The response
+    /// must have been generated with `n_probs` set.
+    ///
+    /// Returns `None` for open-ended answers, or when none of the candidate
+    /// tokens lead to an allowed answer.
+    pub fn distribution(&self, response: &ChatResponse) -> Option<Vec<(String, f32)>> {
+        let choices = self.choices()?;
+        let probs = answer_probs(&choices, &response.token_probs);
+
+        let total: f32 = probs.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        Some(
+            choices
+                .into_iter()
+                .zip(probs)
+                .map(|(choice, prob)| (choice.to_string(), prob / total))
+                .collect(),
+        )
+    }
+
+    /// The allowed answer the model was most likely to give, with its
+    /// probability, as estimated by [`QueryAnswers::distribution`].
+    pub fn most_likely(&self, response: &ChatResponse) -> Option<(String, f32)> {
+        self.distribution(response)?
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+}
+
+/// Spreads the probability of each candidate token over the answers it is a
+/// prefix of. When several answers share a token, the following tokens of
+/// the response decide between them, as long as the response went that way.
+fn answer_probs(answers: &[&str], tokens: &[TokenProbs]) -> Vec<f32> {
+    let mut probs = vec![0.0; answers.len()];
+    let Some((first, rest)) = tokens.split_first() else {
+        return probs;
+    };
+
+    for candidate in &first.top {
+        // An answer which is already complete is followed by the newline.
+        let matching = answers
+            .iter()
+            .enumerate()
+            .filter(|(_, answer)| match answer.is_empty() {
+                true => candidate.token.starts_with('\n'),
+                false => !candidate.token.is_empty() && answer.starts_with(&candidate.token),
+            })
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        let mut shares = vec![1.0; matching.len()];
+        if matching.len() > 1
+            && candidate.token == first.token
+            && !candidate.token.starts_with('\n')
+        {
+            let remaining = matching
+                .iter()
+                .map(|&i| &answers[i][candidate.token.len() ..])
+                .collect::<Vec<_>>();
+            let following = answer_probs(&remaining, rest);
+
+            if following.iter().sum::<f32>() > 0.0 {
+                shares = following;
             }
-            QueryAnswers::String => String::from(r#"root ::= [^\n]+ "\n""#),
-            QueryAnswers::Boolean => String::from(r#"root ::= ("Yes" | "No") "\n""#),
-            QueryAnswers::Number => String::from(r#"root ::= [0-9]+ "\n""#),
         }
+
+        let total: f32 = shares.iter().sum();
+        for (i, share) in matching.into_iter().zip(shares) {
+            probs[i] += candidate.prob * share / total;
+        }
+    }
+
+    probs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grammar::validate_gbnf;
+
+    #[test]
+    fn grammars_are_valid() {
+        let answers = QueryAnswers::Literals(vec![
+            String::from("Say \"hello\""),
+            String::from(r"back\slash"),
+            String::from("two\nlines"),
+        ]);
+
+        assert_eq!(
+            answers.as_grammar(),
+            "root ::= (\"Say \\\"hello\\\"\" | \"back\\\\slash\" | \"two\\nlines\") \"\\n\"\n"
+        );
+
+        let query = MessageAction::Query {
+            question: None,
+            answers,
+        };
+
+        let all = MessageAction::ALL.iter().chain([&query]);
+        for action in all {
+            validate_gbnf(&action.as_grammar()).unwrap();
+        }
+
+        for answers in [
+            QueryAnswers::String,
+            QueryAnswers::Boolean,
+            QueryAnswers::Number,
+        ] {
+            validate_gbnf(&answers.as_grammar()).unwrap();
+        }
+    }
+
+    #[test]
+    fn answer_distribution() {
+        let token = |token: &str, top: &[(&str, f32)]| TokenProbs {
+            token: token.to_string(),
+            top: top
+                .iter()
+                .map(|(token, prob)| crate::llm::TokenProb {
+                    token: token.to_string(),
+                    prob: *prob,
+                })
+                .collect(),
+        };
+
+        let response = ChatResponse {
+            text: String::from("Greenish\n"),
+            prompt_token_count: 0,
+            generated_token_count: 3,
+            generation_time: 0.0,
+            token_probs: vec![
+                token("Green", &[("Green", 0.6), ("Red", 0.3), ("Blue", 0.1)]),
+                token("ish", &[("ish", 0.75), ("\n", 0.25)]),
+                token("\n", &[("\n", 1.0)]),
+            ],
+        };
+
+        let answers = QueryAnswers::Literals(vec![
+            String::from("Red"),
+            String::from("Green"),
+            String::from("Greenish"),
+        ]);
+        let distribution = answers.distribution(&response).unwrap();
+        let expected = [("Red", 1.0 / 3.0), ("Green", 1.0 / 6.0), ("Greenish", 0.5)];
+
+        for ((answer, prob), (expected, expected_prob)) in distribution.iter().zip(expected) {
+            assert_eq!(answer, expected);
+            assert!((prob - expected_prob).abs() < 1e-5, "{}: {}", answer, prob);
+        }
+
+        let (answer, prob) = answers.most_likely(&response).unwrap();
+        assert_eq!(answer, "Greenish");
+        assert!((prob - 0.5).abs() < 1e-5);
+
+        assert!((response.confidence().unwrap() - 0.45).abs() < 1e-5);
+        assert!(QueryAnswers::String.distribution(&response).is_none());
+        assert!(QueryAnswers::Boolean.distribution(&response).is_none());
+        assert!(QueryAnswers::Boolean.most_likely(&response).is_none());
     }
 }
@@ -0,0 +1,100 @@
+use thiserror::Error;
+
+/// A command the agent can send in the COMMAND state. Commands are split
+/// into arguments like a shell would, so text with spaces may be quoted:
+/// `memory append user_facts "Likes cats"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentCommand {
+    MemoryAppend { slot: String, text: String },
+    MemoryReplace { slot: String, text: String },
+    MemoryClear { slot: String },
+}
+
+impl AgentCommand {
+    /// The syntax and purpose of each command, as shown to the agent.
+    pub const USAGE: [&'static str; 3] = [
+        "memory append <slot> <text>: Adds a line to a slot of your active memory context.",
+        "memory replace <slot> <text>: Replaces the contents of a slot of your active memory context.",
+        "memory clear <slot>: Empties a slot of your active memory context.",
+    ];
+
+    pub fn parse(line: &str) -> Result<Self, CommandError> {
+        let args = shlex::split(line.trim()).ok_or(CommandError::InvalidQuoting)?;
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+        match args.as_slice() {
+            ["memory", "append", slot, text @ ..] if !text.is_empty() => {
+                Ok(AgentCommand::MemoryAppend {
+                    slot: slot.to_string(),
+                    text: text.join(" "),
+                })
+            }
+            ["memory", "replace", slot, text @ ..] if !text.is_empty() => {
+                Ok(AgentCommand::MemoryReplace {
+                    slot: slot.to_string(),
+                    text: text.join(" "),
+                })
+            }
+            ["memory", "clear", slot] => Ok(AgentCommand::MemoryClear {
+                slot: slot.to_string(),
+            }),
+            ["memory", ..] => Err(CommandError::InvalidArguments("memory")),
+            [] => Err(CommandError::Empty),
+            [name, ..] => Err(CommandError::UnknownCommand(name.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("The command is empty")]
+    Empty,
+    #[error("The command has unbalanced quotes")]
+    InvalidQuoting,
+    #[error("Unknown command `{0}`")]
+    UnknownCommand(String),
+    #[error("Invalid arguments for command `{0}`")]
+    InvalidArguments(&'static str),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            AgentCommand::parse("memory append user_facts \"Likes cats\"\n").unwrap(),
+            AgentCommand::MemoryAppend {
+                slot: String::from("user_facts"),
+                text: String::from("Likes cats"),
+            }
+        );
+        assert_eq!(
+            AgentCommand::parse("memory replace current_goal Learn to paint").unwrap(),
+            AgentCommand::MemoryReplace {
+                slot: String::from("current_goal"),
+                text: String::from("Learn to paint"),
+            }
+        );
+        assert_eq!(
+            AgentCommand::parse("memory clear current_goal").unwrap(),
+            AgentCommand::MemoryClear {
+                slot: String::from("current_goal"),
+            }
+        );
+
+        assert!(matches!(
+            AgentCommand::parse("memory append user_facts"),
+            Err(CommandError::InvalidArguments("memory"))
+        ));
+        assert!(matches!(
+            AgentCommand::parse("memory append \"user_facts"),
+            Err(CommandError::InvalidQuoting)
+        ));
+        assert!(matches!(
+            AgentCommand::parse("Look around"),
+            Err(CommandError::UnknownCommand(name)) if name == "Look"
+        ));
+    }
+}
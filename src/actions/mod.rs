@@ -1,5 +1,7 @@
 mod action;
+mod command;
 mod statemachine;
 
 pub use action::*;
+pub use command::*;
 pub use statemachine::*;
@@ -13,8 +13,9 @@ impl ProcessStateMachine {
         self.last_action.as_ref()
     }
 
-    pub fn next_action(&mut self) -> &MessageAction {
-        let next_action = match self.last_action {
+    /// The action after the last one, without moving on to it.
+    pub fn upcoming_action(&self) -> MessageAction {
+        match self.last_action {
             None => MessageAction::SituationalAnalysis,
             Some(MessageAction::SituationalAnalysis) => MessageAction::EmotionalResponse,
             Some(MessageAction::EmotionalResponse) => MessageAction::LogicalResponse,
@@ -26,9 +27,12 @@ impl ProcessStateMachine {
             Some(MessageAction::Command) => MessageAction::Say,
             Some(MessageAction::Say) => MessageAction::SituationalAnalysis,
             Some(MessageAction::Query { .. }) => MessageAction::SituationalAnalysis,
-        };
+        }
+    }
 
-        self.last_action = Some(next_action);
+    /// Moves on to the upcoming action.
+    pub fn next_action(&mut self) -> &MessageAction {
+        self.last_action = Some(self.upcoming_action());
         self.last_action.as_ref().unwrap()
     }
 }
@@ -1,13 +1,27 @@
 use chrono::Local;
+use futures::StreamExt;
 use itertools::Itertools;
-use log::{debug, info};
+use log::{debug, info, warn};
 
 use super::{AgentError, AgentSettings};
-use crate::actions::{MessageAction, ProcessStateMachine};
+use crate::actions::{AgentCommand, MessageAction, ProcessStateMachine};
 use crate::communications::CommunicationManager;
-use crate::llm::LlmWrapper;
-use crate::mem_db::MemoryDB;
-use crate::prompt::{ChatMessage, ACTION_STATE, SYSTEM_PROMPT};
+use crate::grammar::{Expr, Grammar};
+use crate::llm::{CompletionChunk, CompletionSettings, LLMError, LlmWrapper};
+use crate::mem_db::{MemoryDB, MemoryDBError, MemoryFilter, DEFAULT_IMPORTANCE};
+use crate::prompt::{
+    ChatMessage,
+    ChatRole,
+    JinjaChatTemplate,
+    SystemMessageSeverity,
+    ACTION_STATE,
+    IMPORTANCE_PROMPT,
+    SUMMARY_PROMPT,
+    SYSTEM_PROMPT,
+};
+
+/// How many of the newest messages are used to search the vector memory.
+const RECALL_QUERY_MESSAGES: usize = 3;
 
 pub struct Agent {
     pub settings: AgentSettings,
@@ -15,16 +29,31 @@ pub struct Agent {
     pub mem_db: MemoryDB,
     pub communication_manager: CommunicationManager,
     pub process_state_machine: ProcessStateMachine,
+
+    /// The chat template embedded in the model, if the backend exposes one.
+    pub chat_template: Option<JinjaChatTemplate>,
 }
 
 impl Agent {
-    pub async fn new(settings: AgentSettings, llm: LlmWrapper) -> Result<Self, AgentError> {
+    pub async fn new(mut settings: AgentSettings, llm: LlmWrapper) -> Result<Self, AgentError> {
+        let chat_template = llm.chat_template().await?;
+        if let Some(template) = &chat_template {
+            info!("Using the chat template of the model");
+
+            let stop_tokens = &mut settings.llm_options.stop_tokens;
+            if !template.eos_token.is_empty() && !stop_tokens.contains(&template.eos_token) {
+                stop_tokens.push(template.eos_token.clone());
+            }
+        }
+
+        let mem_db = MemoryDB::new(&settings.memory, &settings.backend).await?;
         let mut agent = Self {
             settings,
             llm,
-            mem_db: MemoryDB::new().await?,
+            mem_db,
             communication_manager: CommunicationManager::default(),
             process_state_machine: ProcessStateMachine::default(),
+            chat_template,
         };
         agent.update_system_prompt().await?;
 
@@ -32,14 +61,92 @@ impl Agent {
     }
 
     pub async fn update(&mut self) -> Result<(), AgentError> {
-        for mut message in self.communication_manager.receive_messages().await {
-            self.update_token_count(&mut message).await?;
+        // Received messages are logged before anything can fail, since they
+        // cannot be received again.
+        for message in self.communication_manager.receive_messages().await {
             self.mem_db.add_log_memory(message);
         }
+        self.count_log_tokens().await?;
 
         let response = self.query_llm().await?;
+        let command = match &response {
+            ChatMessage::Assistant {
+                action: MessageAction::Command,
+                content,
+                ..
+            } => Some(content.clone()),
+            _ => None,
+        };
         self.log_message(response).await?;
 
+        if let Some(command) = command {
+            self.run_command(&command).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a command sent in the COMMAND state, and tells the agent how it
+    /// went.
+    async fn run_command(&mut self, line: &str) -> Result<(), AgentError> {
+        let result = match AgentCommand::parse(line) {
+            Ok(command) => self
+                .execute_command(&command)
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        let (severity, content) = match result {
+            Ok(content) => {
+                self.update_system_prompt().await?;
+                (SystemMessageSeverity::Info, content)
+            }
+            Err(err) => (SystemMessageSeverity::Warn, err),
+        };
+
+        self.log_message(ChatMessage::System {
+            severity,
+            content,
+            tokens: None,
+        })
+        .await
+    }
+
+    fn execute_command(&mut self, command: &AgentCommand) -> Result<String, MemoryDBError> {
+        let memory = self.mem_db.working_memory_mut();
+
+        match command {
+            AgentCommand::MemoryAppend { slot, text } => {
+                memory.append(slot, text)?;
+                Ok(format!("Added to memory slot `{}`.", slot))
+            }
+            AgentCommand::MemoryReplace { slot, text } => {
+                memory.replace(slot, text)?;
+                Ok(format!("Replaced memory slot `{}`.", slot))
+            }
+            AgentCommand::MemoryClear { slot } => {
+                memory.clear(slot)?;
+                Ok(format!("Cleared memory slot `{}`.", slot))
+            }
+        }
+    }
+
+    /// Counts the tokens of the logged messages which have not been counted
+    /// yet.
+    async fn count_log_tokens(&mut self) -> Result<(), AgentError> {
+        let uncounted = self
+            .mem_db
+            .log_messages()
+            .iter()
+            .positions(|message| message.get_tokens().is_none())
+            .collect::<Vec<_>>();
+
+        for i in uncounted {
+            let mut message = self.mem_db.log_messages()[i].clone();
+            self.update_token_count(&mut message).await?;
+            self.mem_db.log_messages_mut()[i] = message;
+        }
+
         Ok(())
     }
 
@@ -48,7 +155,12 @@ impl Agent {
             return Ok(());
         }
 
-        let content = message.format(&self.settings.llm_options);
+        // The markup of a chat template depends on the messages around this
+        // one, so it is counted for the whole prompt in `fit_context`.
+        let content = match &self.chat_template {
+            Some(_) => message.get_content(),
+            None => message.format(&self.settings.llm_options),
+        };
         let tokens = self.llm.tokenize(content).await?;
         message.set_tokens(tokens.len());
 
@@ -61,14 +173,297 @@ impl Agent {
         Ok(())
     }
 
+    /// Formats the message log into a prompt which ends with an open
+    /// assistant turn. The chat template of the model is preferred, falling
+    /// back to the configured message prefixes.
+    fn build_prompt(&mut self) -> String {
+        if let Some(template) = &self.chat_template {
+            match self.mem_db.render_log_prompt(template) {
+                Ok(prompt) => return prompt,
+                Err(err) => {
+                    warn!(
+                        "Failed to render the chat template of the model, using message prefixes instead: {}",
+                        err
+                    );
+                    self.chat_template = None;
+                }
+            }
+        }
+
+        let settings = &self.settings.llm_options;
+        let (assistant_prefix, _) = settings
+            .chat_template
+            .affixes(ChatRole::Assistant, settings);
+        self.mem_db.get_log_prompt(settings) + assistant_prefix
+    }
+
+    /// Drops the oldest messages from the log until the prompt fits in the
+    /// context window of the model, leaving room for the response.
+    async fn fit_context(&mut self) -> Result<(), AgentError> {
+        let Some(mut budget) = self.settings.llm_options.context_budget() else {
+            return Ok(());
+        };
+
+        // Leave room for the summary to grow up to its limit.
+        if let Some(limit) = self.settings.memory.summary_tokens {
+            budget = budget.saturating_sub(limit.saturating_sub(self.mem_db.summary_token_count()));
+        }
+
+        // Leave room for the markup the prompt has around the messages.
+        let prompt = self.build_prompt();
+        let prompt_tokens = self.llm.tokenize(prompt).await?.len();
+        budget = budget.saturating_sub(prompt_tokens.saturating_sub(self.mem_db.log_token_count()));
+
+        let evicted = self.mem_db.log_overflow(budget).to_vec();
+        if evicted.is_empty() {
+            return Ok(());
+        }
+
+        // The messages are only dropped once they are kept elsewhere, so a
+        // failed request loses nothing, and is tried again on the next turn.
+        self.on_evicted(&evicted).await?;
+        self.mem_db.evict_log_memory(evicted.len());
+
+        info!("Evicted {} messages from the context window", evicted.len());
+        Ok(())
+    }
+
+    /// Called with the messages which no longer fit in the context window,
+    /// oldest first, so they are not forgotten entirely.
+    async fn on_evicted(&mut self, messages: &[ChatMessage]) -> Result<(), AgentError> {
+        let mut importance = Vec::with_capacity(messages.len());
+        for message in messages {
+            importance.push(self.rate_importance(message).await?);
+        }
+
+        // Everything is asked of the model before anything is stored, so
+        // nothing is stored when a request fails.
+        let summary = match self.settings.memory.summary_tokens {
+            Some(limit) => Some(self.summarize_evicted(messages, limit).await?),
+            None => None,
+        };
+
+        self.mem_db
+            .add_vector_memories(messages.iter().zip(importance))
+            .await?;
+
+        let Some((part, condensed)) = summary else {
+            return Ok(());
+        };
+
+        self.mem_db.add_summary(part.0, part.1);
+        if let Some((summary, tokens)) = condensed {
+            self.mem_db.condense_summary(summary, tokens);
+        }
+
+        if let Some(mut message) = self.mem_db.summary_message() {
+            self.update_token_count(&mut message).await?;
+            self.mem_db.update_log_summary(message);
+        }
+
+        Ok(())
+    }
+
+    /// Summarizes the evicted messages into a new part of the summary. When
+    /// the summary would grow past `limit` tokens, it is also condensed,
+    /// and the condensed summary replaces every part.
+    async fn summarize_evicted(
+        &self,
+        messages: &[ChatMessage],
+        limit: usize,
+    ) -> Result<((String, usize), Option<(String, usize)>), AgentError> {
+        let transcript = messages
+            .iter()
+            .map(|m| format!("{}: {}", m.get_role(), m.get_content()))
+            .join("\n");
+        let (part, tokens) = self.summarize(transcript, limit).await?;
+
+        if self.mem_db.summary_token_count() + tokens <= limit {
+            return Ok(((part, tokens), None));
+        }
+
+        info!("Condensing the conversation summary");
+        let summary = match self.mem_db.get_summary() {
+            summary if summary.is_empty() => part.clone(),
+            summary => format!("{}\n{}", summary, part),
+        };
+        let condensed = self.summarize(summary, limit).await?;
+
+        Ok(((part, tokens), Some(condensed)))
+    }
+
+    /// Asks the model to summarize the text in at most `max_tokens` tokens,
+    /// returning the summary and its token count.
+    async fn summarize(
+        &self,
+        text: String,
+        max_tokens: usize,
+    ) -> Result<(String, usize), AgentError> {
+        let settings = CompletionSettings {
+            max_tokens: max_tokens as i32,
+            stop_tokens: Vec::new(),
+            grammar: None,
+            n_probs: None,
+            ..self.settings.llm_options.clone()
+        };
+
+        let summary = self.run_task(SUMMARY_PROMPT, text, &settings).await?;
+        debug!("Summarized messages: {}", summary);
+
+        let tokens = self.llm.tokenize(summary.clone()).await?.len();
+        Ok((summary, tokens))
+    }
+
+    /// Asks the model how important a message is to remember, from 0 to 1.
+    async fn rate_importance(&self, message: &ChatMessage) -> Result<f32, AgentError> {
+        if self.settings.memory.retrieval.importance == 0.0 {
+            return Ok(DEFAULT_IMPORTANCE);
+        }
+
+        let ratings = (1 ..= 10).map(|rating| rating.to_string());
+        let settings = CompletionSettings {
+            max_tokens: 4,
+            grammar: Some(Grammar::new(Expr::one_of(ratings)).to_string()),
+            n_probs: None,
+            ..self.settings.llm_options.clone()
+        };
+
+        let text = format!("{}: {}", message.get_role(), message.get_content());
+        let rating = self.run_task(IMPORTANCE_PROMPT, text, &settings).await?;
+
+        match rating.parse::<u8>() {
+            Ok(rating) => Ok((rating.clamp(1, 10) - 1) as f32 / 9.0),
+            Err(_) => {
+                warn!("Invalid importance rating `{}`", rating);
+                Ok(DEFAULT_IMPORTANCE)
+            }
+        }
+    }
+
+    /// Asks the model to follow the instructions for the text, outside of the
+    /// conversation.
+    async fn run_task(
+        &self,
+        instructions: &str,
+        text: String,
+        settings: &CompletionSettings,
+    ) -> Result<String, AgentError> {
+        let messages = [
+            ChatMessage::System {
+                severity: SystemMessageSeverity::Info,
+                content: instructions
+                    .trim()
+                    .replace("{ai_name}", &self.settings.name),
+                tokens: None,
+            },
+            ChatMessage::User {
+                username: String::from("Text"),
+                channel: None,
+                content: text,
+                tokens: None,
+            },
+        ];
+
+        let rendered = self
+            .chat_template
+            .as_ref()
+            .and_then(|template| template.render(&messages, true).ok());
+        let prompt = match rendered {
+            Some(prompt) => prompt,
+            None => {
+                let (assistant_prefix, _) = settings
+                    .chat_template
+                    .affixes(ChatRole::Assistant, settings);
+                messages.iter().map(|m| m.format(settings)).join("") + assistant_prefix
+            }
+        };
+
+        let response = self.llm.query_completion(prompt, settings).await?;
+        Ok(response.text.trim().to_string())
+    }
+
+    /// Searches the vector memory for memories related to the latest
+    /// messages, and shows the closest ones to the agent for its next
+    /// response.
+    async fn recall_memories(&mut self) -> Result<(), AgentError> {
+        let settings = &self.settings.memory;
+        let query = self
+            .mem_db
+            .recent_log_memory(RECALL_QUERY_MESSAGES)
+            .iter()
+            .map(|message| message.get_content())
+            .join("\n");
+
+        if settings.recall_count == 0 || query.is_empty() {
+            self.mem_db.set_recalled_memories(None);
+            return Ok(());
+        }
+
+        let memories = self
+            .mem_db
+            .search_vector_memory(
+                &query,
+                settings.recall_count,
+                settings.recall_min_similarity,
+                &MemoryFilter::default(),
+                &settings.retrieval,
+            )
+            .await?;
+
+        let mut lines = Vec::new();
+        let mut ids = Vec::new();
+        let mut tokens = 0;
+        for memory in memories {
+            let line = format!("- {}", memory.text.replace('\n', " "));
+            let count = self.llm.tokenize(line.clone()).await?.len();
+            if tokens + count > settings.recall_tokens {
+                break;
+            }
+
+            tokens += count;
+            lines.push(line);
+            ids.push(memory.id);
+        }
+        self.mem_db.mark_recalled(&ids)?;
+
+        let recalled = match lines.is_empty() {
+            true => None,
+            false => {
+                debug!("Recalled {} memories", lines.len());
+                let mut message = ChatMessage::System {
+                    severity: SystemMessageSeverity::Info,
+                    content: format!("Recalled memories:\n{}", lines.join("\n")),
+                    tokens: None,
+                };
+                self.update_token_count(&mut message).await?;
+                Some(message)
+            }
+        };
+
+        self.mem_db.set_recalled_memories(recalled);
+        Ok(())
+    }
+
     async fn query_llm(&mut self) -> Result<ChatMessage, AgentError> {
-        let mut prompt = self.mem_db.get_log_prompt(&self.settings.llm_options);
-        let action = self.process_state_machine.next_action();
+        self.recall_memories().await?;
+        self.fit_context().await?;
+
+        let mut prompt = self.build_prompt();
+        let action = self.process_state_machine.upcoming_action();
         let prefix = action.as_prompt();
 
-        prompt += &self.settings.llm_options.assistant_message_prefix;
         prompt += &prefix;
-        self.settings.llm_options.grammar = Some(action.as_grammar());
+        let mut settings = CompletionSettings {
+            grammar: Some(action.as_grammar()),
+            ..self.settings.llm_options.clone()
+        };
+
+        // Enough candidates for each token to tell every allowed answer apart.
+        if let MessageAction::Query { answers, .. } = &action {
+            if let Some(choices) = answers.choices() {
+                settings.n_probs = Some(choices.len() as u32);
+            }
+        }
 
         debug!(
             "Querying LLM with prompt:\n==========\n{}\n==========",
@@ -76,34 +471,80 @@ impl Agent {
         );
         info!("Querying LLM with prompt prefix: `{}`", &prefix);
 
+        let mut retries = 0;
         let response = loop {
-            let response = self
+            let mut stream = self
                 .llm
-                .query_completion(prompt.clone(), &self.settings.llm_options)
+                .stream_completion(prompt.clone(), &settings)
                 .await?;
 
+            let mut partial = String::new();
+            let mut response = None;
+
+            while let Some(chunk) = stream.next().await {
+                match chunk? {
+                    CompletionChunk::Token(token) => {
+                        if !self.communication_manager.has_partial_channels() {
+                            continue;
+                        }
+
+                        partial += &token;
+                        self.communication_manager
+                            .send_partial_message(&ChatMessage::Assistant {
+                                action: action.clone(),
+                                content: partial.clone(),
+                                tokens: None,
+                            });
+                    }
+                    CompletionChunk::Done(done) => response = Some(done),
+                }
+            }
+
+            let response = response.ok_or(LLMError::UnexpectedEndOfStream)?;
             if !response.is_empty() {
-                break response.text;
+                break response;
+            }
+
+            if retries >= self.llm.retry_policy().max_retries {
+                return Err(LLMError::EmptyResponse.into());
             }
 
+            retries += 1;
             debug!("LLM response was empty, retrying...");
         };
 
-        info!("LLM response: {:?}", &response);
+        info!("LLM response: {:?}", &response.text);
 
-        let mut message = ChatMessage::Assistant {
-            action: action.clone(),
-            content: response,
-            tokens: None,
-        };
-        self.update_token_count(&mut message).await?;
+        // A query is answered with the answer the model was most likely to
+        // give, rather than the one which happened to be sampled.
+        let mut content = response.text.clone();
+        if let MessageAction::Query { answers, .. } = &action {
+            if let Some((answer, prob)) = answers.most_likely(&response) {
+                info!("Most likely answer: {:?} ({:.2})", answer, prob);
+                content = answer;
+            }
+        }
+
+        // Only move on once the action has a response, so a failed request
+        // is tried again with the same action.
+        self.process_state_machine.next_action();
 
-        Ok(message)
+        Ok(ChatMessage::Assistant {
+            action,
+            content,
+            tokens: None,
+        })
     }
 
     pub async fn update_system_prompt(&mut self) -> Result<(), AgentError> {
         let time = &Local::now().format("%Y-%m-%d").to_string();
-        let memory_context = "EMPTY";
+        let memory_context = &self.mem_db.working_memory().render();
+        let slot_names = self.mem_db.working_memory().slot_names().join(", ");
+        let command_list = AgentCommand::USAGE
+            .iter()
+            .map(|usage| format!("- {}", usage))
+            .chain([format!("Memory slots: {}", slot_names)])
+            .join("\n");
         let action_states = MessageAction::ALL
             .iter()
             .map(|s| {
@@ -118,7 +559,7 @@ impl Agent {
             .replace("{time}", time)
             .replace("{ai_name}", &self.settings.name)
             .replace("{creator}", &self.settings.creator)
-            .replace("{command_list}", "")
+            .replace("{command_list}", &command_list)
             .replace("{personality}", &self.settings.persona)
             .replace("{memory_context}", memory_context)
             .replace("{primary_directive}", &self.settings.directive)
@@ -135,11 +576,313 @@ impl Agent {
         Ok(())
     }
 
-    pub async fn log_message(&mut self, mut message: ChatMessage) -> Result<(), AgentError> {
-        self.update_token_count(&mut message).await?;
+    /// Sends the message to the communication channels and logs it. Its
+    /// tokens are counted once it is logged, so it is not lost when that
+    /// fails.
+    pub async fn log_message(&mut self, message: ChatMessage) -> Result<(), AgentError> {
         self.communication_manager.send_message(&message).await;
         self.mem_db.add_log_memory(message);
+        self.count_log_tokens().await
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::mock::MockLlm;
+    use crate::mem_db::{
+        EmbedderSettings,
+        MemorySettings,
+        RecalledMemory,
+        RetrievalWeights,
+        VectorIndexType,
+    };
+
+    async fn mock_agent(mock: &MockLlm, memory: MemorySettings) -> Agent {
+        let settings = AgentSettings {
+            name: String::from("Lily"),
+            creator: String::from("Test"),
+            persona: String::from("Curious"),
+            directive: String::from("Chat"),
+            backend: Default::default(),
+            llm_options: CompletionSettings::default(),
+            memory: MemorySettings {
+                embedder: EmbedderSettings::Hashing { dimension: 256 },
+                vector_index: VectorIndexType::BruteForce,
+                retrieval: RetrievalWeights {
+                    importance: 0.0,
+                    ..Default::default()
+                },
+                ..memory
+            },
+        };
+
+        Agent::new(settings, mock.clone().into()).await.unwrap()
+    }
+
+    async fn search(agent: &Agent, query: &str) -> Vec<RecalledMemory> {
+        let filter = MemoryFilter::default();
+        let weights = &agent.settings.memory.retrieval;
+        let memories = agent
+            .mem_db
+            .search_vector_memory(query, 1, 0.9, &filter, weights);
+        memories.await.unwrap()
+    }
+
+    fn user_message(content: &str) -> ChatMessage {
+        ChatMessage::User {
+            username: String::from("Tester"),
+            channel: None,
+            content: content.to_string(),
+            tokens: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_response_is_recoverable() {
+        let mock = MockLlm::new();
+        for action in MessageAction::ALL.iter() {
+            mock.respond_to_action(action, "");
+        }
+        let mut agent = mock_agent(&mock, MemorySettings::default()).await;
+
+        let err = agent.update().await.unwrap_err();
+        assert!(matches!(err, AgentError::LLMError(LLMError::EmptyResponse)));
+        assert!(err.is_recoverable());
+    }
+
+    #[tokio::test]
+    async fn retry_action_after_failure() {
+        let mock = MockLlm::new();
+        let mut agent = mock_agent(&mock, MemorySettings::default()).await;
+        let incoming = agent.communication_manager.open_incoming_channel("test");
+        incoming
+            .send_message(user_message("Hi Lily!"))
+            .await
+            .unwrap();
+
+        // Without a response, the update fails, but the received message is
+        // logged and the action is asked for again.
+        assert!(agent.update().await.is_err());
+        assert_eq!(agent.process_state_machine.last_action(), None);
+        let messages = agent.mem_db.log_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].get_content(), "Tester: Hi Lily!");
+        assert!(messages[1].get_tokens().is_some());
+
+        let action = MessageAction::SituationalAnalysis;
+        mock.respond_to_action(&action, "Someone greeted me.");
+        agent.update().await.unwrap();
+        assert_eq!(agent.process_state_machine.last_action(), Some(&action));
+        assert_eq!(agent.mem_db.log_messages().len(), 3);
+
+        let prompts = mock.prompts();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts
+            .iter()
+            .all(|prompt| prompt.ends_with(&action.as_prompt())));
+    }
+
+    #[tokio::test]
+    async fn only_say_reaches_outgoing_channel() {
+        let mock = MockLlm::new();
+        for action in MessageAction::ALL.iter().skip(1) {
+            mock.respond_to_action(action, action.name());
+        }
+        let mut agent = mock_agent(&mock, MemorySettings::default()).await;
+        let incoming = agent.communication_manager.open_incoming_channel("test");
+        let outgoing = agent.communication_manager.open_outgoing_channel("test");
+        incoming
+            .send_message(user_message("Hi Lily!"))
+            .await
+            .unwrap();
+
+        // A full cycle of the state machine, ending in SAY. The COMMAND
+        // response is not a valid command, so a warning is logged as well.
+        for _ in 0 .. 9 {
+            agent.update().await.unwrap();
+        }
+
+        let received = outgoing.receive_messages().await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(matches!(
+            &received[0],
+            ChatMessage::Assistant {
+                action: MessageAction::Say,
+                content,
+                ..
+            } if content == "SAY"
+        ));
+
+        let prompts = mock.prompts();
+        assert_eq!(prompts.len(), 9);
+        assert!(prompts[0].ends_with("SITUATIONAL_ANALYSIS: "));
+        assert!(prompts[8].ends_with(&MessageAction::Say.as_prompt()));
+
+        // The system prompt, the user message, nine responses and the
+        // warning.
+        assert_eq!(agent.mem_db.log_messages().len(), 12);
+    }
+
+    #[tokio::test]
+    async fn keep_messages_until_archived() {
+        let mock = MockLlm::new();
+        let memory = MemorySettings {
+            summary_tokens: None,
+            ..Default::default()
+        };
+        let mut agent = mock_agent(&mock, memory).await;
+        agent.settings.memory.retrieval.importance = 1.0;
+
+        // Room for the pre-prompt and one message.
+        let pre_prompt = MockLlm::tokenize_text(&agent.build_prompt()).len();
+        agent.settings.llm_options.max_tokens = 10;
+        agent.settings.llm_options.context_size = Some(pre_prompt + 10 + 12);
+
+        for content in ["I planted apple trees.", "The pears never grew."] {
+            agent.log_message(user_message(content)).await.unwrap();
+        }
+
+        // Without a response, rating the evicted message fails, and it stays
+        // in the log.
+        assert!(agent.fit_context().await.is_err());
+        assert_eq!(agent.mem_db.log_messages().len(), 3);
+        assert!(search(&agent, "I planted apple trees.").await.is_empty());
+
+        mock.push_response("7");
+        agent.fit_context().await.unwrap();
+        assert_eq!(agent.mem_db.log_messages().len(), 2);
+
+        let memories = search(&agent, "I planted apple trees.").await;
+        assert_eq!(memories.len(), 1);
+        assert_eq!(memories[0].metadata.importance, 6.0 / 9.0);
+    }
+
+    #[tokio::test]
+    async fn summarize_evicted_messages() {
+        let mock = MockLlm::new();
+        let memory = MemorySettings {
+            summary_tokens: Some(12),
+            ..Default::default()
+        };
+        let mut agent = mock_agent(&mock, memory).await;
+
+        // Room for the pre-prompt, the summary and about two messages.
+        let pre_prompt = MockLlm::tokenize_text(&agent.build_prompt()).len();
+        agent.settings.llm_options.max_tokens = 10;
+        agent.settings.llm_options.context_size = Some(pre_prompt + 10 + 12 + 24);
+
+        let conversation = [
+            "I planted apple trees in the garden last spring.",
+            "The pears next to them never grew any fruit.",
+            "My sister visits every Sunday to bake pies.",
+            "We are going sailing on the lake next week.",
+        ];
+        for content in conversation {
+            agent.log_message(user_message(content)).await.unwrap();
+        }
+
+        // Without a summary, nothing is evicted.
+        assert!(agent.fit_context().await.is_err());
+        assert_eq!(agent.mem_db.log_messages().len(), 5);
+        assert!(search(&agent, conversation[0]).await.is_empty());
+
+        mock.push_response("Tester grows apples and pears.");
+        agent.fit_context().await.unwrap();
+        assert!(mock.prompts()[1].contains("user: Tester: I planted apple trees"));
+
+        let messages = agent.mem_db.log_messages();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(
+            messages[1].get_content(),
+            "[INFO] Conversation so far:\nTester grows apples and pears."
+        );
+        assert_eq!(agent.mem_db.summary_token_count(), 5);
+
+        // The evicted messages are archived to the vector memory.
+        for content in &conversation[.. 2] {
+            let memories = search(&agent, content).await;
+            assert_eq!(memories.len(), 1, "{}", content);
+            assert!(memories[0].text.ends_with(content));
+        }
+
+        // A second summary takes the summary past its limit, so both are
+        // condensed into one.
+        agent
+            .log_message(user_message("The boat has a red sail and two oars."))
+            .await
+            .unwrap();
+        agent
+            .log_message(user_message(
+                "Afterwards we will eat the pies on the shore.",
+            ))
+            .await
+            .unwrap();
+        let part = "Tester's sister bakes pies every Sunday before sailing.";
+
+        // Without a condensed summary, the summary is left as it was.
+        mock.push_response(part);
+        assert!(agent.fit_context().await.is_err());
+        assert_eq!(agent.mem_db.log_messages().len(), 6);
+        assert_eq!(agent.mem_db.get_summary(), "Tester grows apples and pears.");
+
+        mock.push_response(part)
+            .push_response("Tester gardens and has a sister.");
+        agent.fit_context().await.unwrap();
+
+        let prompts = mock.prompts();
+        assert_eq!(prompts.len(), 6);
+        assert!(prompts[5].contains(
+            "Tester grows apples and pears.\nTester's sister bakes pies every Sunday before sailing."
+        ));
+
+        let messages = agent.mem_db.log_messages();
+        assert_eq!(
+            messages[1].get_content(),
+            "[INFO] Conversation so far:\nTester gardens and has a sister."
+        );
+        assert_eq!(agent.mem_db.summary_token_count(), 6);
+    }
+
+    #[tokio::test]
+    async fn recall_after_newest_message() {
+        let mock = MockLlm::new();
+        let memory = MemorySettings {
+            recall_count: 3,
+            recall_tokens: 20,
+            recall_min_similarity: 0.6,
+            ..Default::default()
+        };
+        let mut agent = mock_agent(&mock, memory).await;
+
+        // Each recalled line, like `- user: Tester: I picked apples in the
+        // orchard.`, is nine tokens, so only two fit.
+        let memories = [
+            "I picked apples in the orchard.",
+            "I sold apples at the market.",
+            "I ate apples with the pie.",
+        ];
+        for content in memories {
+            let message = user_message(content);
+            agent.mem_db.add_vector_memory(&message, 0.5).await.unwrap();
+        }
+
+        agent
+            .log_message(user_message("Where did the apples go?"))
+            .await
+            .unwrap();
+        agent.recall_memories().await.unwrap();
+
+        let prompt = agent.mem_db.get_log_prompt(&agent.settings.llm_options);
+        let newest = prompt.find("Where did the apples go?").unwrap();
+        let recalled = prompt.find("Recalled memories:").unwrap();
+        assert!(newest < recalled);
+
+        let lines = prompt[recalled ..]
+            .lines()
+            .filter(|line| line.starts_with("- user: Tester: I "))
+            .count();
+        assert_eq!(lines, 2);
+        assert!(mock.prompts().is_empty());
     }
 }
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
 use crate::llm::LLMError;
@@ -14,3 +16,27 @@ pub enum AgentError {
     #[error("An error has occurred within the LLM: {0}")]
     LLMError(#[from] LLMError),
 }
+
+impl AgentError {
+    /// Whether the agent can keep running after this error.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            AgentError::LLMError(err) => err.is_recoverable(),
+            AgentError::MemoryDBError(MemoryDBError::EmbeddingRequestFailed(err)) => {
+                err.is_recoverable()
+            }
+            _ => false,
+        }
+    }
+
+    /// How long to wait before updating the agent again after this error.
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            AgentError::LLMError(err) => err.retry_after(),
+            AgentError::MemoryDBError(MemoryDBError::EmbeddingRequestFailed(err)) => {
+                err.retry_after()
+            }
+            _ => Duration::from_secs(1),
+        }
+    }
+}
@@ -4,7 +4,8 @@ use log::debug;
 use serde::{Deserialize, Serialize};
 
 use super::AgentError;
-use crate::llm::CompletionSettings;
+use crate::llm::{BackendSettings, CompletionSettings};
+use crate::mem_db::MemorySettings;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AgentSettings {
@@ -12,7 +13,11 @@ pub struct AgentSettings {
     pub creator: String,
     pub persona: String,
     pub directive: String,
+    #[serde(default)]
+    pub backend: BackendSettings,
     pub llm_options: CompletionSettings,
+    #[serde(default)]
+    pub memory: MemorySettings,
 }
 
 impl AgentSettings {
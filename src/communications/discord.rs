@@ -120,6 +120,7 @@ impl EventHandler for ProjectLilyDiscordHandler {
             .channel
             .send_message(ChatMessage::User {
                 username: msg.author.name,
+                channel: Some(String::from("discord")),
                 content: msg.content.clone(),
                 tokens: None,
             })
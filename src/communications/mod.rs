@@ -1,18 +1,20 @@
 use std::fmt;
 
 use thiserror::Error;
-use tokio::sync::mpsc::error::{SendError, TryRecvError};
+use tokio::sync::mpsc::error::{SendError, TryRecvError, TrySendError};
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::sync::RwLock;
 
 pub mod discord;
 
+use crate::actions::MessageAction;
 use crate::prompt::ChatMessage;
 
 pub struct CommunicationManager {
     two_way_channels: Vec<TwoWayChannel>,
     incoming_channels: Vec<OneWayChannelReceiver>,
     outgoing_channels: Vec<OneWayChannelSender>,
+    partial_channels: Vec<OneWayChannelSender>,
 }
 
 impl CommunicationManager {
@@ -21,6 +23,7 @@ impl CommunicationManager {
             two_way_channels: Vec::new(),
             incoming_channels: Vec::new(),
             outgoing_channels: Vec::new(),
+            partial_channels: Vec::new(),
         }
     }
 
@@ -48,6 +51,8 @@ impl CommunicationManager {
         to_agent
     }
 
+    /// Opens a channel that receives only what the agent says to the user,
+    /// which are its SAY messages. Two-way channels receive every message.
     pub fn open_outgoing_channel(&mut self, name: &str) -> OneWayChannelReceiver {
         let (to_external, external) = open_channel(name);
 
@@ -55,6 +60,16 @@ impl CommunicationManager {
         external
     }
 
+    /// Opens a channel that receives assistant messages while they are still
+    /// being generated. Each message contains the full content generated so
+    /// far. Partial messages are dropped if the channel is full.
+    pub fn open_partial_channel(&mut self, name: &str) -> OneWayChannelReceiver {
+        let (to_external, external) = open_channel(name);
+
+        self.partial_channels.push(to_external);
+        external
+    }
+
     pub async fn receive_messages(&self) -> Vec<ChatMessage> {
         let mut messages = Vec::new();
 
@@ -82,12 +97,37 @@ impl CommunicationManager {
             };
         }
 
+        let is_said = matches!(
+            message,
+            ChatMessage::Assistant {
+                action: MessageAction::Say,
+                ..
+            }
+        );
+        if !is_said {
+            return;
+        }
+
         for channel in &self.outgoing_channels {
             if (channel.send_message(message.clone()).await).is_err() {
                 println!("Failed to send message to two-way channel: {}", channel);
             };
         }
     }
+
+    pub fn has_partial_channels(&self) -> bool {
+        !self.partial_channels.is_empty()
+    }
+
+    pub fn send_partial_message(&self, message: &ChatMessage) {
+        for channel in &self.partial_channels {
+            if let Err(CommunicationsError::ChannelClosed(_)) =
+                channel.try_send_message(message.clone())
+            {
+                println!("Failed to send message to partial channel: {}", channel);
+            };
+        }
+    }
 }
 
 impl Default for CommunicationManager {
@@ -140,6 +180,11 @@ impl OneWayChannelSender {
         self.tx.send(message).await?;
         Ok(())
     }
+
+    pub fn try_send_message(&self, message: ChatMessage) -> Result<(), CommunicationsError> {
+        self.tx.try_send(message)?;
+        Ok(())
+    }
 }
 
 impl fmt::Display for OneWayChannelSender {
@@ -197,6 +242,17 @@ pub enum CommunicationsError {
     EndOfStream,
     #[error("Channel closed: failed to send")]
     ChannelClosed(#[from] SendError<ChatMessage>),
+    #[error("Channel full: failed to send")]
+    ChannelFull,
+}
+
+impl From<TrySendError<ChatMessage>> for CommunicationsError {
+    fn from(err: TrySendError<ChatMessage>) -> Self {
+        match err {
+            TrySendError::Full(_) => CommunicationsError::ChannelFull,
+            TrySendError::Closed(message) => CommunicationsError::ChannelClosed(SendError(message)),
+        }
+    }
 }
 
 fn open_channel(name: &str) -> (OneWayChannelSender, OneWayChannelReceiver) {
@@ -0,0 +1,282 @@
+use std::fmt;
+
+use super::{literal, repeat, validate_gbnf, GrammarError};
+
+/// An expression within a GBNF rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// Text which is matched exactly. Escaping is done when formatting.
+    Literal(String),
+    /// A reference to another rule by name.
+    Rule(String),
+    Class(CharClass),
+    Sequence(Vec<Expr>),
+    Alternatives(Vec<Expr>),
+    Repeat {
+        expr: Box<Expr>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+impl Expr {
+    pub fn literal(text: impl Into<String>) -> Self {
+        Expr::Literal(text.into())
+    }
+
+    pub fn rule(name: impl Into<String>) -> Self {
+        Expr::Rule(name.into())
+    }
+
+    pub fn sequence(items: impl IntoIterator<Item = Expr>) -> Self {
+        Expr::Sequence(items.into_iter().collect())
+    }
+
+    pub fn alternatives(items: impl IntoIterator<Item = Expr>) -> Self {
+        Expr::Alternatives(items.into_iter().collect())
+    }
+
+    /// Matches one of the given texts exactly.
+    pub fn one_of<S: Into<String>>(texts: impl IntoIterator<Item = S>) -> Self {
+        Expr::alternatives(texts.into_iter().map(Expr::literal))
+    }
+
+    pub fn repeat(self, min: usize, max: Option<usize>) -> Self {
+        Expr::Repeat {
+            expr: Box::new(self),
+            min,
+            max,
+        }
+    }
+
+    pub fn optional(self) -> Self {
+        self.repeat(0, Some(1))
+    }
+
+    pub fn zero_or_more(self) -> Self {
+        self.repeat(0, None)
+    }
+
+    pub fn one_or_more(self) -> Self {
+        self.repeat(1, None)
+    }
+
+    /// Whether the expression needs parentheses when it is repeated or part
+    /// of a sequence.
+    fn is_compound(&self) -> bool {
+        match self {
+            Expr::Sequence(items) | Expr::Alternatives(items) => items.len() > 1,
+            Expr::Repeat { min, max, .. } => !matches!((min, max), (0 | 1, None) | (0, Some(1))),
+            _ => false,
+        }
+    }
+
+    fn fmt_grouped(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.is_compound() {
+            true => write!(f, "({})", self),
+            false => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(text) => write!(f, "{}", literal(text)),
+            Expr::Rule(name) => write!(f, "{}", name),
+            Expr::Class(class) => write!(f, "{}", class),
+            Expr::Sequence(items) | Expr::Alternatives(items) if items.is_empty() => {
+                write!(f, "\"\"")
+            }
+            Expr::Sequence(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+
+                    match item {
+                        Expr::Alternatives(_) => item.fmt_grouped(f)?,
+                        item => write!(f, "{}", item)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Alternatives(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Expr::Repeat { expr, min, max } => {
+                let mut inner = String::new();
+                match expr.is_compound() {
+                    true => inner += &format!("({})", expr),
+                    false => inner += &expr.to_string(),
+                }
+
+                match (min, max) {
+                    (0, Some(1)) => write!(f, "{}?", inner),
+                    (0, None) => write!(f, "{}*", inner),
+                    (1, None) => write!(f, "{}+", inner),
+                    (min, max) => write!(f, "{}", repeat(&inner, *min, *max)),
+                }
+            }
+        }
+    }
+}
+
+impl From<CharClass> for Expr {
+    fn from(class: CharClass) -> Self {
+        Expr::Class(class)
+    }
+}
+
+/// A set of characters, such as `[a-z_]` or `[^\n]`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CharClass {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    /// Matches any of the given characters.
+    pub fn any_of(chars: &str) -> Self {
+        Self::default().chars(chars)
+    }
+
+    /// Matches any character except the given ones.
+    pub fn none_of(chars: &str) -> Self {
+        Self {
+            negated: true,
+            ..Self::default().chars(chars)
+        }
+    }
+
+    pub fn chars(mut self, chars: &str) -> Self {
+        self.ranges.extend(chars.chars().map(|c| (c, c)));
+        self
+    }
+
+    pub fn range(mut self, from: char, to: char) -> Self {
+        self.ranges.push((from, to));
+        self
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn escape(c: char) -> String {
+            match c {
+                '\\' | ']' | '[' => format!("\\{}", c),
+                // These have a meaning depending on their position.
+                '^' | '-' => format!("\\x{:02X}", c as u32),
+                '\n' => String::from("\\n"),
+                '\r' => String::from("\\r"),
+                '\t' => String::from("\\t"),
+                c if c.is_control() => format!("\\x{:02X}", c as u32),
+                c => c.to_string(),
+            }
+        }
+
+        write!(f, "[")?;
+        if self.negated {
+            write!(f, "^")?;
+        }
+
+        for (from, to) in &self.ranges {
+            match from == to {
+                true => write!(f, "{}", escape(*from))?,
+                false => write!(f, "{}-{}", escape(*from), escape(*to))?,
+            }
+        }
+
+        write!(f, "]")
+    }
+}
+
+/// A GBNF grammar built from named rules, starting at `root`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grammar {
+    rules: Vec<(String, Expr)>,
+}
+
+impl Grammar {
+    pub fn new(root: Expr) -> Self {
+        Self {
+            rules: vec![(String::from("root"), root)],
+        }
+    }
+
+    pub fn rule(mut self, name: impl Into<String>, expr: Expr) -> Self {
+        self.rules.push((name.into(), expr));
+        self
+    }
+
+    /// Checks that the grammar is well-formed, and that every rule it refers
+    /// to exists.
+    pub fn validate(&self) -> Result<(), GrammarError> {
+        validate_gbnf(&self.to_string())
+    }
+}
+
+impl fmt::Display for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, expr) in &self.rules {
+            writeln!(f, "{} ::= {}", name, expr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escape_tricky_literals() {
+        let answers = ["say \"hi\"", r"C:\path", "two\nlines", "[x]", "", "ünïcode"];
+        let grammar = Grammar::new(Expr::one_of(answers));
+
+        assert_eq!(
+            grammar.to_string(),
+            "root ::= \"say \\\"hi\\\"\" | \"C:\\\\path\" | \"two\\nlines\" | \"[x]\" | \"\" | \"ünïcode\"\n"
+        );
+        grammar.validate().unwrap();
+    }
+
+    #[test]
+    fn build_multi_line_shape() {
+        let line = Expr::from(CharClass::none_of("\n")).one_or_more();
+        let grammar = Grammar::new(Expr::sequence([
+            Expr::literal("QUERY: "),
+            Expr::rule("line"),
+            Expr::literal("\nANSWER: "),
+            Expr::one_of(["Yes", "No"]),
+            Expr::literal("\n"),
+            Expr::sequence([Expr::literal("- "), Expr::rule("line")]).repeat(1, Some(3)),
+        ]))
+        .rule("line", Expr::sequence([line, Expr::literal("\n")]));
+
+        assert_eq!(
+            grammar.to_string(),
+            concat!(
+                "root ::= \"QUERY: \" line \"\\nANSWER: \" (\"Yes\" | \"No\") \"\\n\" (\"- \" line) ((\"- \" line) (\"- \" line)?)?\n",
+                "line ::= [^\\n]+ \"\\n\"\n",
+            )
+        );
+        grammar.validate().unwrap();
+    }
+
+    #[test]
+    fn escape_char_classes() {
+        let class = CharClass::any_of("]-^\\").range('a', 'z');
+        assert_eq!(class.to_string(), r"[\]\x2D\x5E\\a-z]");
+        Grammar::new(class.into()).validate().unwrap();
+
+        let missing = Grammar::new(Expr::rule("missing"));
+        assert!(missing.validate().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Generates GBNF grammars, which constrain the output of the language model
+//! through [`CompletionSettings::grammar`](crate::llm::CompletionSettings).
+
+use thiserror::Error;
+
+mod builder;
+mod pattern;
+mod schema;
+mod typed;
+mod validate;
+
+pub use builder::*;
+pub use schema::*;
+pub use typed::*;
+pub use validate::*;
+
+#[derive(Debug, Error)]
+pub enum GrammarError {
+    #[error("Unsupported JSON schema at `{path}`: {reason}")]
+    UnsupportedSchema { path: String, reason: String },
+    #[error("Failed to resolve schema reference: {0}")]
+    UnresolvedReference(String),
+    #[error("Unsupported pattern `{pattern}`: {reason}")]
+    UnsupportedPattern { pattern: String, reason: String },
+    #[error("Invalid grammar at line {line}: {reason}")]
+    InvalidGrammar { line: usize, reason: String },
+    #[error(
+        "Response does not match the expected type at line {line}, column {column}: {message}"
+    )]
+    ResponseMismatch {
+        message: String,
+        line: usize,
+        column: usize,
+        response: String,
+    },
+}
+
+/// Formats text as a quoted GBNF literal.
+pub fn literal(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c.is_control() => literal.push_str(&format!("\\x{:02X}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
+
+/// Repeats an expression between `min` and `max` times, or any number of
+/// times above `min` without a maximum. Repetitions are written out, since
+/// older versions of llama.cpp do not support `{m,n}`.
+pub(crate) fn repeat(expr: &str, min: usize, max: Option<usize>) -> String {
+    let expr = group(expr);
+    let mut parts = vec![expr.clone(); min];
+
+    match max {
+        None => parts.push(format!("{}*", expr)),
+        Some(max) if max > min => {
+            let optional = (min .. max).fold(String::new(), |rest, _| match rest.is_empty() {
+                true => format!("{}?", expr),
+                false => format!("({} {})?", expr, rest),
+            });
+            parts.push(optional);
+        }
+        Some(_) => {}
+    }
+
+    parts.join(" ")
+}
+
+/// Wraps an expression in parentheses unless it is a single term.
+pub(crate) fn group(expr: &str) -> String {
+    match is_single_term(expr) {
+        true => expr.to_string(),
+        false => format!("({})", expr),
+    }
+}
+
+/// Whether an expression has no spaces or alternations outside of literals,
+/// character classes and parentheses.
+fn is_single_term(expr: &str) -> bool {
+    let mut depth = 0;
+    let mut in_literal = false;
+    let mut in_class = false;
+    let mut escaped = false;
+
+    for c in expr.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' => escaped = true,
+            '"' if !in_class => in_literal = !in_literal,
+            '[' if !in_literal => in_class = true,
+            ']' if !in_literal => in_class = false,
+            _ if in_literal || in_class => {}
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ' ' | '|' if depth == 0 => return false,
+            _ => {}
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escape_literals() {
+        assert_eq!(literal(r#"say "hi"\n"#), r#""say \"hi\"\\n""#);
+        assert_eq!(literal("a\nb"), r#""a\nb""#);
+    }
+
+    #[test]
+    fn repeat_expressions() {
+        assert_eq!(repeat("x", 2, Some(2)), "x x");
+        assert_eq!(repeat("x", 1, None), "x x*");
+        assert_eq!(repeat("x y", 0, Some(2)), "((x y) (x y)?)?");
+        assert_eq!(repeat(r#""a b""#, 1, Some(2)), r#""a b" "a b"?"#);
+        assert_eq!(repeat("(a | b)", 0, None), "(a | b)*");
+    }
+}
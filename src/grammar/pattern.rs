@@ -0,0 +1,301 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::{group, literal, repeat, GrammarError};
+
+/// Any character allowed unescaped inside a JSON string.
+pub(crate) const STRING_CHAR: &str = r#"[^"\\\x7F\x00-\x1F]"#;
+
+/// Translates a regular expression into a GBNF expression matching the
+/// contents of a JSON string. The whole string has to match, as if the
+/// pattern was anchored at both ends.
+///
+/// Supports literals, `.`, character classes, the `\d`, `\w` and `\s`
+/// shorthands, groups, alternations and the `*`, `+`, `?` and `{m,n}`
+/// quantifiers.
+pub(crate) fn pattern_to_gbnf(pattern: &str) -> Result<String, GrammarError> {
+    let trimmed = pattern.strip_prefix('^').unwrap_or(pattern);
+    let trimmed = trimmed.strip_suffix('$').unwrap_or(trimmed);
+
+    let mut parser = PatternParser {
+        pattern,
+        chars: trimmed.chars().peekable(),
+    };
+
+    let expr = parser.alternation()?;
+    match parser.chars.next() {
+        Some(c) => Err(parser.error(format!("unexpected `{}`", c))),
+        None => Ok(expr),
+    }
+}
+
+struct PatternParser<'a> {
+    pattern: &'a str,
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> PatternParser<'a> {
+    fn error(&self, reason: impl Into<String>) -> GrammarError {
+        GrammarError::UnsupportedPattern {
+            pattern: self.pattern.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    fn alternation(&mut self) -> Result<String, GrammarError> {
+        let mut alternatives = vec![self.sequence()?];
+
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            alternatives.push(self.sequence()?);
+        }
+
+        Ok(alternatives.join(" | "))
+    }
+
+    fn sequence(&mut self) -> Result<String, GrammarError> {
+        let mut items: Vec<String> = Vec::new();
+        let mut text = String::new();
+
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+
+            let atom = self.atom()?;
+            let quantified = self.quantifier(&atom)?;
+
+            // Consecutive plain characters are merged into a single literal.
+            match (quantified, atom) {
+                (None, Atom::Text(s)) => text += &s,
+                (quantified, atom) => {
+                    if !text.is_empty() {
+                        items.push(literal(&std::mem::take(&mut text)));
+                    }
+                    items.push(quantified.unwrap_or_else(|| atom.into_expr()));
+                }
+            }
+        }
+
+        if !text.is_empty() {
+            items.push(literal(&text));
+        }
+
+        match items.is_empty() {
+            true => Ok(String::from("\"\"")),
+            false => Ok(items.join(" ")),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Atom, GrammarError> {
+        let c = self
+            .chars
+            .next()
+            .ok_or_else(|| self.error("unexpected end"))?;
+
+        match c {
+            '(' => {
+                if self.chars.peek() == Some(&'?') {
+                    self.chars.next();
+                    if self.chars.next() != Some(':') {
+                        return Err(self.error("only non-capturing `(?:` groups are supported"));
+                    }
+                }
+
+                let inner = self.alternation()?;
+                if self.chars.next() != Some(')') {
+                    return Err(self.error("unclosed group"));
+                }
+
+                Ok(Atom::Expr(format!("({})", inner)))
+            }
+            '[' => self.class(),
+            '.' => Ok(Atom::Expr(STRING_CHAR.to_string())),
+            '\\' => self.escape(),
+            '^' | '$' => Err(self.error("anchors are only supported at the ends")),
+            '*' | '+' | '?' | '{' => Err(self.error(format!("nothing to repeat before `{}`", c))),
+            c => Ok(Atom::Text(json_escape(c))),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Atom, GrammarError> {
+        let c = self
+            .chars
+            .next()
+            .ok_or_else(|| self.error("unexpected end"))?;
+
+        let atom = match c {
+            'd' => Atom::Expr(String::from("[0-9]")),
+            'D' => Atom::Expr(String::from(r#"[^0-9"\\\x7F\x00-\x1F]"#)),
+            'w' => Atom::Expr(String::from("[a-zA-Z0-9_]")),
+            'W' => Atom::Expr(String::from(r#"[^a-zA-Z0-9_"\\\x7F\x00-\x1F]"#)),
+            's' => Atom::Text(String::from(" ")),
+            'S' => Atom::Expr(String::from(r#"[^ "\\\x7F\x00-\x1F]"#)),
+            'n' => Atom::Text(String::from("\\n")),
+            't' => Atom::Text(String::from("\\t")),
+            'r' => Atom::Text(String::from("\\r")),
+            c if c.is_ascii_alphanumeric() => {
+                return Err(self.error(format!("unsupported escape `\\{}`", c)));
+            }
+            c => Atom::Text(json_escape(c)),
+        };
+
+        Ok(atom)
+    }
+
+    fn class(&mut self) -> Result<Atom, GrammarError> {
+        let mut class = String::from("[");
+
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+            class.push('^');
+        }
+
+        loop {
+            let c = self
+                .chars
+                .next()
+                .ok_or_else(|| self.error("unclosed class"))?;
+
+            match c {
+                ']' => break,
+                '\\' => {
+                    let c = self
+                        .chars
+                        .next()
+                        .ok_or_else(|| self.error("unexpected end"))?;
+                    match c {
+                        'd' => class.push_str("0-9"),
+                        'w' => class.push_str("a-zA-Z0-9_"),
+                        's' => class.push(' '),
+                        '"' | '\\' => {
+                            return Err(self.error("quotes and backslashes in classes"));
+                        }
+                        c if c.is_ascii_alphanumeric() => {
+                            return Err(self.error(format!("unsupported escape `\\{}`", c)));
+                        }
+                        // GBNF only knows a few escapes, so these are written
+                        // by their code, like `CharClass` does.
+                        '-' | ']' | '^' => class.push_str(&format!("\\x{:02X}", c as u32)),
+                        '[' => class.push_str("\\["),
+                        c => class.push(c),
+                    }
+                }
+                '"' => return Err(self.error("quotes and backslashes in classes")),
+                c => class.push(c),
+            }
+        }
+
+        // A negated class must not match characters which need escaping.
+        if negated {
+            class.push_str(r#""\\\x7F\x00-\x1F"#);
+        }
+
+        class.push(']');
+        Ok(Atom::Expr(class))
+    }
+
+    fn quantifier(&mut self, atom: &Atom) -> Result<Option<String>, GrammarError> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                let mut bounds = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break,
+                        Some(c) => bounds.push(c),
+                        None => return Err(self.error("unclosed quantifier")),
+                    }
+                }
+
+                let parse = |s: &str| s.trim().parse::<usize>().ok();
+                let bounds = match bounds.split_once(',') {
+                    None => parse(&bounds).map(|n| (n, Some(n))),
+                    Some((min, "")) => parse(min).map(|min| (min, None)),
+                    Some((min, max)) => parse(min).zip(parse(max)).map(|(a, b)| (a, Some(b))),
+                };
+
+                let bounds = bounds.ok_or_else(|| self.error("invalid quantifier"))?;
+                return Ok(Some(repeat(&atom.to_expr(), bounds.0, bounds.1)));
+            }
+            _ => return Ok(None),
+        };
+
+        self.chars.next();
+        Ok(Some(repeat(&atom.to_expr(), min, max)))
+    }
+}
+
+enum Atom {
+    /// Text which is matched literally, already escaped for a JSON string.
+    Text(String),
+    Expr(String),
+}
+
+impl Atom {
+    fn to_expr(&self) -> String {
+        match self {
+            Atom::Text(text) => literal(text),
+            Atom::Expr(expr) => group(expr),
+        }
+    }
+
+    fn into_expr(self) -> String {
+        match self {
+            Atom::Text(text) => literal(&text),
+            Atom::Expr(expr) => expr,
+        }
+    }
+}
+
+/// Escapes a character as it would appear inside a JSON string.
+fn json_escape(c: char) -> String {
+    let json = serde_json::to_string(&c.to_string()).unwrap_or_default();
+    json[1 .. json.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grammar::validate_gbnf;
+
+    #[test]
+    fn translate_patterns() {
+        assert_eq!(pattern_to_gbnf("^abc$").unwrap(), r#""abc""#);
+        assert_eq!(
+            pattern_to_gbnf(r"\d{3}-\d{2,}").unwrap(),
+            r#"[0-9] [0-9] [0-9] "-" [0-9] [0-9] [0-9]*"#
+        );
+        assert_eq!(
+            pattern_to_gbnf("(?:yes|no)!?").unwrap(),
+            r#"("yes" | "no") "!"?"#
+        );
+        assert_eq!(
+            pattern_to_gbnf("[^a]+").unwrap(),
+            r#"[^a"\\\x7F\x00-\x1F] [^a"\\\x7F\x00-\x1F]*"#
+        );
+        assert_eq!(pattern_to_gbnf(r"[\.\-\]]").unwrap(), r"[.\x2D\x5D]");
+        assert!(pattern_to_gbnf("(a").is_err());
+        assert!(pattern_to_gbnf(r"\p{L}").is_err());
+    }
+
+    #[test]
+    fn escaped_punctuation_is_valid_gbnf() {
+        let patterns = [
+            r"[\.\-]+",
+            r"[^\]\[\^]",
+            r"[a-z\.\(\)\{\}\*\+\?\|\$/]*",
+            r"\(\d+\)\.\-",
+        ];
+
+        for pattern in patterns {
+            let gbnf = pattern_to_gbnf(pattern).unwrap();
+            validate_gbnf(&format!("root ::= {}\n", gbnf))
+                .unwrap_or_else(|err| panic!("{} for {:?}: {}", err, pattern, gbnf));
+        }
+    }
+}
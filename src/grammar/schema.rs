@@ -0,0 +1,442 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use serde_json::Value;
+
+use super::pattern::{pattern_to_gbnf, STRING_CHAR};
+use super::{literal, repeat, GrammarError};
+
+/// Rules for plain JSON values, added to a grammar when they are used.
+const PRIMITIVES: [(&str, &str, &[&str]); 10] = [
+    (
+        "value",
+        "object | array | string | number | boolean | null",
+        &["object", "array", "string", "number", "boolean", "null"],
+    ),
+    (
+        "object",
+        r#""{" ws (string ws ":" ws value ws ("," ws string ws ":" ws value ws)*)? "}""#,
+        &["ws", "string", "value"],
+    ),
+    (
+        "array",
+        r#""[" ws (value ws ("," ws value ws)*)? "]""#,
+        &["ws", "value"],
+    ),
+    ("string", r#""\"" char* "\"""#, &["char"]),
+    (
+        "char",
+        r#"[^"\\\x7F\x00-\x1F] | "\\" (["\\/bfnrt] | "u" [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F])"#,
+        &[],
+    ),
+    (
+        "number",
+        r#"integer ("." [0-9]+)? ([eE] [-+]? [0-9]+)?"#,
+        &["integer"],
+    ),
+    ("integer", r#""-"? ("0" | [1-9] [0-9]*)"#, &[]),
+    ("boolean", r#""true" | "false""#, &[]),
+    ("null", r#""null""#, &[]),
+    // Newlines are left out, since they usually stop the completion.
+    ("ws", r#"" "?"#, &[]),
+];
+
+/// Compiles a JSON Schema into a GBNF grammar whose `root` rule matches a
+/// single line of JSON conforming to the schema.
+///
+/// Supports `type` (including lists of types), `properties` with `required`
+/// keys, `items` with `minItems` and `maxItems`, `enum`, `const`, `anyOf`,
+/// `oneOf`, local `$ref`s, and strings with `pattern`, `minLength` and
+/// `maxLength`. Required properties are written first, and both required
+/// and optional ones in the order of the schema's keys. Any other keywords
+/// are ignored.
+pub fn schema_to_gbnf(schema: &Value) -> Result<String, GrammarError> {
+    let mut compiler = SchemaCompiler {
+        root: schema,
+        rules: Vec::new(),
+        refs: HashMap::new(),
+    };
+
+    let expr = compiler.expression(schema, "root")?;
+    compiler.add_rule("root", expr);
+
+    Ok(compiler.format())
+}
+
+struct SchemaCompiler<'a> {
+    root: &'a Value,
+    rules: Vec<(String, String)>,
+    refs: HashMap<String, String>,
+}
+
+impl<'a> SchemaCompiler<'a> {
+    fn format(&self) -> String {
+        let root = self.rules.iter().filter(|(name, _)| name == "root");
+        let others = self.rules.iter().filter(|(name, _)| name != "root");
+
+        root.chain(others)
+            .map(|(name, body)| format!("{} ::= {}\n", name, body))
+            .join("")
+    }
+
+    /// Adds a rule, renaming it if another rule already has the name, and
+    /// returns the name it was added under.
+    fn add_rule(&mut self, name: &str, body: String) -> String {
+        let name = sanitize(name);
+        let mut unique = name.clone();
+
+        for i in 1 .. {
+            let existing = self.rules.iter().find(|(n, _)| *n == unique);
+            match existing {
+                Some((_, b)) if *b == body => return unique,
+                None if !is_primitive(&unique) => break,
+                _ => unique = format!("{}-{}", name, i),
+            }
+        }
+
+        self.rules.push((unique.clone(), body));
+        unique
+    }
+
+    fn use_primitive(&mut self, name: &str) -> String {
+        if !self.rules.iter().any(|(n, _)| n == name) {
+            let (_, body, deps) = PRIMITIVES.iter().find(|(n, ..)| *n == name).unwrap();
+
+            // Added before its dependencies, since some refer back to it.
+            self.rules.push((name.to_string(), body.to_string()));
+            for dep in deps.iter() {
+                self.use_primitive(dep);
+            }
+        }
+
+        name.to_string()
+    }
+
+    fn error(&self, path: &str, reason: impl Into<String>) -> GrammarError {
+        GrammarError::UnsupportedSchema {
+            path: path.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns an expression matching the schema, adding rules for any
+    /// nested schemas under names starting with `name`.
+    fn expression(&mut self, schema: &Value, name: &str) -> Result<String, GrammarError> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok(self.use_primitive("value")),
+            Value::Bool(false) => return Err(self.error(name, "schema never matches")),
+            Value::Object(schema) => schema,
+            _ => return Err(self.error(name, "schema must be an object")),
+        };
+
+        if let Some(reference) = schema.get("$ref") {
+            let reference = reference.as_str().unwrap_or_default();
+            return self.reference(reference);
+        }
+
+        if let Some(value) = schema.get("const") {
+            return Ok(literal(&value.to_string()));
+        }
+
+        if let Some(values) = schema.get("enum") {
+            let values = values
+                .as_array()
+                .ok_or_else(|| self.error(name, "`enum` must be a list"))?;
+            return Ok(values.iter().map(|v| literal(&v.to_string())).join(" | "));
+        }
+
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(schemas) = schema.get(keyword) {
+                let schemas = schemas
+                    .as_array()
+                    .ok_or_else(|| self.error(name, format!("`{}` must be a list", keyword)))?;
+
+                let alternatives = schemas
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| self.rule(s, &format!("{}-{}", name, i)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(alternatives.join(" | "));
+            }
+        }
+
+        if schema.contains_key("allOf") {
+            return Err(self.error(name, "`allOf` is not supported"));
+        }
+
+        let schema_type = match schema.get("type") {
+            Some(Value::String(t)) => t.as_str(),
+            Some(Value::Array(types)) => {
+                let alternatives = types
+                    .iter()
+                    .map(|t| {
+                        let mut schema = schema.clone();
+                        schema.insert(String::from("type"), t.clone());
+                        let t = t.as_str().unwrap_or_default();
+                        self.rule(&Value::Object(schema), &format!("{}-{}", name, t))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(alternatives.join(" | "));
+            }
+            Some(_) => return Err(self.error(name, "`type` must be a string or a list")),
+            None if schema.contains_key("properties") => "object",
+            None if schema.contains_key("items") => "array",
+            None => return Ok(self.use_primitive("value")),
+        };
+
+        match schema_type {
+            "object" => self.object(schema, name),
+            "array" => self.array(schema, name),
+            "string" => self.string(schema, name),
+            "number" | "integer" | "boolean" | "null" => Ok(self.use_primitive(schema_type)),
+            t => Err(self.error(name, format!("unknown type `{}`", t))),
+        }
+    }
+
+    /// Adds a rule for the schema, returning its name.
+    fn rule(&mut self, schema: &Value, name: &str) -> Result<String, GrammarError> {
+        let expr = self.expression(schema, name)?;
+
+        // Plain references to other rules do not need a rule of their own.
+        if expr.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Ok(expr);
+        }
+
+        Ok(self.add_rule(name, expr))
+    }
+
+    fn reference(&mut self, reference: &str) -> Result<String, GrammarError> {
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(name.clone());
+        }
+
+        let target = reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .ok_or_else(|| GrammarError::UnresolvedReference(reference.to_string()))?;
+
+        // The name is reserved first, so recursive schemas refer to it.
+        let name = reference.rsplit('/').next().unwrap_or("ref");
+        let name = self.add_rule(name, String::new());
+        self.refs.insert(reference.to_string(), name.clone());
+
+        let expr = self.expression(target, &name)?;
+        if let Some(rule) = self.rules.iter_mut().find(|(n, _)| *n == name) {
+            rule.1 = expr;
+        }
+
+        Ok(name)
+    }
+
+    fn object(
+        &mut self,
+        schema: &serde_json::Map<String, Value>,
+        name: &str,
+    ) -> Result<String, GrammarError> {
+        let properties = match schema.get("properties") {
+            Some(Value::Object(properties)) => properties,
+            Some(_) => return Err(self.error(name, "`properties` must be an object")),
+            None => return Ok(self.use_primitive("object")),
+        };
+
+        let required = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|keys| keys.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let ws = self.use_primitive("ws");
+        let mut required_members = Vec::new();
+        let mut optional_members = Vec::new();
+
+        for (key, property) in properties {
+            let value = self.rule(property, &format!("{}-{}", name, key))?;
+            let key_literal = literal(&Value::String(key.clone()).to_string());
+            let member = format!("{} {} \":\" {} {} {}", key_literal, ws, ws, value, ws);
+
+            match required.contains(&key.as_str()) {
+                true => required_members.push(member),
+                false => optional_members.push(member),
+            }
+        }
+
+        let separator = format!("\",\" {}", ws);
+        let optional_tail = |members: &[String]| {
+            members
+                .iter()
+                .map(|m| format!(" ({} {})?", separator, m))
+                .join("")
+        };
+
+        let members = match (required_members.is_empty(), optional_members.is_empty()) {
+            (true, true) => String::new(),
+            (false, _) => {
+                let required = required_members.join(&format!(" {} ", separator));
+                format!(" {}{}", required, optional_tail(&optional_members))
+            }
+
+            // Without required members, any of the optional ones may come
+            // first, followed by any of those after it.
+            (true, false) => {
+                let alternatives = (0 .. optional_members.len())
+                    .map(|i| {
+                        let first = &optional_members[i];
+                        format!("{}{}", first, optional_tail(&optional_members[i + 1 ..]))
+                    })
+                    .join(" | ");
+                format!(" ({})?", alternatives)
+            }
+        };
+
+        Ok(format!("\"{{\" {}{} \"}}\"", ws, members))
+    }
+
+    fn array(
+        &mut self,
+        schema: &serde_json::Map<String, Value>,
+        name: &str,
+    ) -> Result<String, GrammarError> {
+        let item = match schema.get("items") {
+            Some(Value::Array(_)) => return Err(self.error(name, "tuples are not supported")),
+            Some(items) => self.rule(items, &format!("{}-item", name))?,
+            None => self.use_primitive("value"),
+        };
+
+        let min = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let max = schema
+            .get("maxItems")
+            .and_then(Value::as_u64)
+            .map(|n| n as usize);
+
+        let ws = self.use_primitive("ws");
+        let item = format!("{} {}", item, ws);
+        let next = format!("\",\" {} {}", ws, item);
+
+        let items = match (min, max) {
+            (_, Some(0)) => String::new(),
+            (0, max) => format!(" ({} {})?", item, repeat(&next, 0, max.map(|n| n - 1))),
+            (min, max) => format!(" {} {}", item, repeat(&next, min - 1, max.map(|n| n - 1))),
+        };
+
+        Ok(format!("\"[\" {}{} \"]\"", ws, items))
+    }
+
+    fn string(
+        &mut self,
+        schema: &serde_json::Map<String, Value>,
+        name: &str,
+    ) -> Result<String, GrammarError> {
+        if let Some(pattern) = schema.get("pattern") {
+            let pattern = pattern
+                .as_str()
+                .ok_or_else(|| self.error(name, "`pattern` must be a string"))?;
+            return Ok(format!("\"\\\"\" {} \"\\\"\"", pattern_to_gbnf(pattern)?));
+        }
+
+        let min = schema.get("minLength").and_then(Value::as_u64);
+        let max = schema.get("maxLength").and_then(Value::as_u64);
+
+        if min.is_none() && max.is_none() {
+            return Ok(self.use_primitive("string"));
+        }
+
+        let chars = repeat(
+            STRING_CHAR,
+            min.unwrap_or(0) as usize,
+            max.map(|n| n as usize),
+        );
+        Ok(format!("\"\\\"\" {} \"\\\"\"", chars))
+    }
+}
+
+fn is_primitive(name: &str) -> bool {
+    PRIMITIVES.iter().any(|(n, ..)| *n == name)
+}
+
+/// Replaces characters which are not allowed in rule names.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '-',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::grammar::validate_gbnf;
+
+    #[test]
+    fn compile_object_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "emotion": { "enum": ["happy", "sad", "say \"hi\""] },
+                "intensity": { "type": "number" },
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 2 },
+            },
+            "required": ["emotion"],
+        });
+
+        let grammar = schema_to_gbnf(&schema).unwrap();
+        validate_gbnf(&grammar).unwrap();
+        let lines = grammar.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            lines[0],
+            r#"root ::= "{" ws "\"emotion\"" ws ":" ws root-emotion ws ("," ws "\"intensity\"" ws ":" ws number ws)? ("," ws "\"tags\"" ws ":" ws root-tags ws)? "}""#
+        );
+        assert!(
+            lines.contains(&r#"root-emotion ::= "\"happy\"" | "\"sad\"" | "\"say \\\"hi\\\"\"""#)
+        );
+        assert!(lines.contains(&r#"root-tags ::= "[" ws (string ws ("," ws string ws)?)? "]""#));
+        assert!(lines.contains(&r#"ws ::= " "?"#));
+        assert!(lines.iter().any(|l| l.starts_with("char ::= ")));
+    }
+
+    #[test]
+    fn keep_property_order() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "thought": { "type": "string" },
+                "action": { "type": "string" },
+                "zeal": { "type": "integer" },
+                "answer": { "type": "string" },
+            },
+            "required": ["thought", "action", "answer"],
+        });
+
+        let grammar = schema_to_gbnf(&schema).unwrap();
+        let keys = ["thought", "action", "answer", "zeal"]
+            .map(|key| grammar.find(&format!("\"\\\"{}\\\"\"", key)).unwrap());
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]), "{}", grammar);
+    }
+
+    #[test]
+    fn compile_recursive_reference() {
+        let schema = json!({
+            "$ref": "#/$defs/node",
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "properties": {
+                        "children": { "type": "array", "items": { "$ref": "#/$defs/node" } },
+                    },
+                },
+            },
+        });
+
+        let grammar = schema_to_gbnf(&schema).unwrap();
+        validate_gbnf(&grammar).unwrap();
+        assert!(grammar.starts_with("root ::= node\n"));
+        assert!(
+            grammar.contains(r#"node ::= "{" ws ("\"children\"" ws ":" ws node-children ws)? "}""#)
+        );
+        assert!(grammar.contains(r#"node-children ::= "[" ws (node ws ("," ws node ws)*)? "]""#));
+    }
+}
@@ -0,0 +1,99 @@
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{schema_to_gbnf, GrammarError};
+use crate::llm::CompletionSettings;
+
+/// Constrains a completion to JSON matching a schema, and parses the
+/// response into `T`.
+pub struct JsonGrammar<T> {
+    schema: Value,
+    grammar: String,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> JsonGrammar<T> {
+    pub fn new(schema: Value) -> Result<Self, GrammarError> {
+        let grammar = schema_to_gbnf(&schema)?;
+
+        Ok(Self {
+            schema,
+            grammar,
+            _type: PhantomData,
+        })
+    }
+
+    pub fn schema(&self) -> &Value {
+        &self.schema
+    }
+
+    pub fn grammar(&self) -> &str {
+        &self.grammar
+    }
+
+    /// Returns a copy of the settings which uses this grammar.
+    pub fn apply(&self, settings: &CompletionSettings) -> CompletionSettings {
+        CompletionSettings {
+            grammar: Some(self.grammar.clone()),
+            ..settings.clone()
+        }
+    }
+
+    pub fn parse(&self, response: &str) -> Result<T, GrammarError> {
+        serde_json::from_str(response.trim()).map_err(|err| GrammarError::ResponseMismatch {
+            message: err.to_string(),
+            line: err.line(),
+            column: err.column(),
+            response: response.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Emotion {
+        name: String,
+        intensity: f32,
+    }
+
+    #[test]
+    fn parse_response() {
+        let grammar = JsonGrammar::<Emotion>::new(json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "intensity": { "type": "number" },
+            },
+            "required": ["name", "intensity"],
+        }))
+        .unwrap();
+
+        let settings = grammar.apply(&CompletionSettings::default());
+        assert_eq!(settings.grammar.as_deref(), Some(grammar.grammar()));
+
+        let emotion = grammar
+            .parse(r#"{ "intensity": 0.5, "name": "calm" }"#)
+            .unwrap();
+        assert_eq!(
+            emotion,
+            Emotion {
+                name: String::from("calm"),
+                intensity: 0.5,
+            }
+        );
+
+        let err = grammar.parse(r#"{ "name": "calm" }"#).unwrap_err();
+        assert!(matches!(
+            err,
+            GrammarError::ResponseMismatch { line: 1, .. }
+        ));
+    }
+}
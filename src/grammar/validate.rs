@@ -0,0 +1,323 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::GrammarError;
+
+/// Checks that a GBNF grammar would be accepted by llama.cpp: every rule is
+/// well-formed, `root` and every referenced rule are defined, and no rule is
+/// defined twice.
+///
+/// llama.cpp ignores a grammar it fails to parse and generates without it,
+/// so this catches mistakes which would otherwise go unnoticed.
+pub fn validate_gbnf(grammar: &str) -> Result<(), GrammarError> {
+    let mut validator = Validator {
+        chars: grammar.chars().peekable(),
+        line: 1,
+        defined: Vec::new(),
+        referenced: Vec::new(),
+    };
+
+    validator.grammar()?;
+
+    if !validator.defined.iter().any(|name| name == "root") {
+        return Err(GrammarError::InvalidGrammar {
+            line: 1,
+            reason: String::from("missing `root` rule"),
+        });
+    }
+
+    for (name, line) in &validator.referenced {
+        if !validator.defined.contains(name) {
+            return Err(GrammarError::InvalidGrammar {
+                line: *line,
+                reason: format!("undefined rule `{}`", name),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+struct Validator<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    defined: Vec<String>,
+    referenced: Vec<(String, usize)>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&self, reason: impl Into<String>) -> GrammarError {
+        GrammarError::InvalidGrammar {
+            line: self.line,
+            reason: reason.into(),
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), GrammarError> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("expected `{}`, found `{}`", expected, c))),
+            None => Err(self.error(format!("expected `{}`, found end", expected))),
+        }
+    }
+
+    /// Skips whitespace and comments. Newlines end a rule, unless they are
+    /// inside parentheses.
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                '\n' if newlines => {}
+                '#' => {
+                    while self.chars.peek().is_some_and(|&c| c != '\n') {
+                        self.next();
+                    }
+                    continue;
+                }
+                _ => break,
+            }
+            self.next();
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_ascii_alphanumeric() && c != '-' {
+                break;
+            }
+            name.push(c);
+            self.next();
+        }
+        name
+    }
+
+    fn grammar(&mut self) -> Result<(), GrammarError> {
+        loop {
+            self.skip_space(true);
+            if self.chars.peek().is_none() {
+                return Ok(());
+            }
+
+            let name = self.name();
+            if name.is_empty() {
+                return Err(self.error("expected a rule name"));
+            }
+            if self.defined.contains(&name) {
+                return Err(self.error(format!("rule `{}` is defined twice", name)));
+            }
+
+            self.skip_space(false);
+            for c in "::=".chars() {
+                self.expect(c)?;
+            }
+
+            self.alternatives(false)?;
+            self.defined.push(name);
+
+            match self.next() {
+                None | Some('\n') => {}
+                Some(c) => return Err(self.error(format!("unexpected `{}`", c))),
+            }
+        }
+    }
+
+    fn alternatives(&mut self, nested: bool) -> Result<(), GrammarError> {
+        self.sequence(nested)?;
+
+        while self.chars.peek() == Some(&'|') {
+            self.next();
+            self.sequence(nested)?;
+        }
+
+        Ok(())
+    }
+
+    fn sequence(&mut self, nested: bool) -> Result<(), GrammarError> {
+        let mut terms = 0;
+
+        loop {
+            self.skip_space(nested);
+
+            match self.chars.peek() {
+                Some('"') => {
+                    self.next();
+                    self.literal()?;
+                }
+                Some('[') => {
+                    self.next();
+                    self.class()?;
+                }
+                Some('(') => {
+                    self.next();
+                    self.alternatives(true)?;
+                    self.skip_space(true);
+                    self.expect(')')?;
+                }
+                Some('.') => {
+                    self.next();
+                }
+                Some(&c) if c.is_ascii_alphanumeric() || c == '-' => {
+                    let name = self.name();
+                    self.referenced.push((name, self.line));
+                }
+                Some('*' | '+' | '?' | '{') if terms == 0 => {
+                    return Err(self.error("nothing to repeat"));
+                }
+                Some('*' | '+' | '?') => {
+                    self.next();
+                    continue;
+                }
+                Some('{') => {
+                    self.next();
+                    self.bounds()?;
+                    continue;
+                }
+                _ if terms == 0 => return Err(self.error("empty alternative")),
+                _ => return Ok(()),
+            }
+
+            terms += 1;
+        }
+    }
+
+    fn bounds(&mut self) -> Result<(), GrammarError> {
+        let mut bounds = String::new();
+        loop {
+            match self.next() {
+                Some('}') => break,
+                Some(c) => bounds.push(c),
+                None => return Err(self.error("unclosed repetition")),
+            }
+        }
+
+        let parse = |s: &str| s.trim().parse::<usize>().ok();
+        let valid = match bounds.split_once(',') {
+            None => parse(&bounds).is_some(),
+            Some((min, "")) => parse(min).is_some(),
+            Some((min, max)) => parse(min).zip(parse(max)).is_some_and(|(a, b)| a <= b),
+        };
+
+        match valid {
+            true => Ok(()),
+            false => Err(self.error(format!("invalid repetition `{{{}}}`", bounds))),
+        }
+    }
+
+    fn literal(&mut self) -> Result<(), GrammarError> {
+        loop {
+            match self.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => {
+                    self.escape()?;
+                }
+                Some('\n') | None => return Err(self.error("unterminated literal")),
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn class(&mut self) -> Result<(), GrammarError> {
+        if self.chars.peek() == Some(&'^') {
+            self.next();
+        }
+
+        let mut previous = None;
+        let mut is_empty = true;
+
+        loop {
+            let c = match self.next() {
+                Some(']') if !is_empty => return Ok(()),
+                Some(']') => return Err(self.error("empty character class")),
+                Some('\\') => self.escape()?,
+                Some('\n') | None => return Err(self.error("unterminated character class")),
+                Some('-') if previous.is_some() && self.chars.peek() != Some(&']') => {
+                    let to = match self.next() {
+                        Some('\\') => self.escape()?,
+                        Some(c) => c,
+                        None => return Err(self.error("unterminated character class")),
+                    };
+
+                    if previous > Some(to) {
+                        return Err(self.error("character range is out of order"));
+                    }
+
+                    previous = None;
+                    continue;
+                }
+                Some(c) => c,
+            };
+
+            previous = Some(c);
+            is_empty = false;
+        }
+    }
+
+    /// Reads an escape sequence after a backslash, returning the character.
+    fn escape(&mut self) -> Result<char, GrammarError> {
+        let digits = match self.next() {
+            Some('n') => return Ok('\n'),
+            Some('r') => return Ok('\r'),
+            Some('t') => return Ok('\t'),
+            Some(c @ ('"' | '\\' | '[' | ']')) => return Ok(c),
+            Some('x') => 2,
+            Some('u') => 4,
+            Some('U') => 8,
+            Some(c) => return Err(self.error(format!("unknown escape `\\{}`", c))),
+            None => return Err(self.error("unterminated escape")),
+        };
+
+        let mut code = 0;
+        for _ in 0 .. digits {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid hex escape"))?;
+            code = code * 16 + digit;
+        }
+
+        char::from_u32(code).ok_or_else(|| self.error("invalid character code"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reject_invalid_grammars() {
+        let valid =
+            "# comment\nroot ::= (\"a\" |\n  item)+ [^\\n\"]* \"\\x41\"{2,3}\nitem ::= [a-z]\n";
+        validate_gbnf(valid).unwrap();
+
+        let invalid = [
+            ("item ::= \"a\"", "missing `root` rule"),
+            ("root ::= \"a\" \"b\"c\"", "unterminated literal"),
+            ("root ::= item", "undefined rule `item`"),
+            ("root ::= (\"a\"", "expected `)`, found end"),
+            ("root ::= [z-a]", "character range is out of order"),
+            ("root ::= \"a\" |", "empty alternative"),
+            (
+                "root ::= \"a\"\nroot ::= \"b\"",
+                "rule `root` is defined twice",
+            ),
+            ("root ::= a_b\na_b ::= \"a\"", "unexpected `_`"),
+            ("root ::= \"a\"\na_b ::= \"a\"", "expected `:`, found `_`"),
+        ];
+
+        for (grammar, reason) in invalid {
+            match validate_gbnf(grammar) {
+                Err(GrammarError::InvalidGrammar { reason: r, .. }) => assert_eq!(r, reason),
+                result => panic!("{:?} for {:?}", result, grammar),
+            }
+        }
+    }
+}
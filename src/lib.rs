@@ -1,6 +1,7 @@
 pub mod actions;
 pub mod agent;
 pub mod communications;
+pub mod grammar;
 pub mod llm;
 pub mod mem_db;
 pub mod prompt;
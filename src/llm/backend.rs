@@ -0,0 +1,246 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::llama_cpp::LlamaCppServer;
+use super::middleware::{
+    JsonlLogLayer,
+    LlmStack,
+    LocalTokenizerLayer,
+    MetricsLayer,
+    ResponseCacheLayer,
+    TokenizeCacheLayer,
+};
+use super::ollama::Ollama;
+use super::openai::OpenAiCompatible;
+use super::{HfTokenizer, LLMError, LlmWrapper, RetryPolicy, LLM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendType {
+    #[default]
+    LlamaCpp,
+    #[serde(rename = "openai")]
+    OpenAi,
+    Ollama,
+}
+
+impl BackendType {
+    pub fn default_url(&self) -> &'static str {
+        match self {
+            BackendType::LlamaCpp => "http://localhost:8080",
+            BackendType::OpenAi => "http://localhost:8000",
+            BackendType::Ollama => "http://localhost:11434",
+        }
+    }
+
+    /// Whether the server cannot count tokens, so a `tokenizer_file` is
+    /// needed. Released Ollama servers have no tokenize endpoint.
+    pub fn needs_tokenizer_file(&self) -> bool {
+        matches!(self, BackendType::Ollama)
+    }
+
+    pub fn default_api_key_env(&self) -> Option<&'static str> {
+        match self {
+            BackendType::OpenAi => Some("OPENAI_API_KEY"),
+            _ => None,
+        }
+    }
+}
+
+/// Describes which LLM server to connect to, and how.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackendSettings {
+    #[serde(rename = "type")]
+    pub backend_type: BackendType,
+
+    /// The base URL of the server. Defaults to the usual local port of the
+    /// selected backend.
+    pub url: Option<String>,
+
+    /// The name of the environment variable holding the API key, if any.
+    pub api_key_env: Option<String>,
+
+    /// How long to wait for a connection to the server to be established.
+    pub connect_timeout_ms: Option<u64>,
+
+    /// How long to wait for a response before the request is retried.
+    pub request_timeout_ms: Option<u64>,
+
+    /// A Hugging Face `tokenizer.json` of the model, used to count tokens
+    /// locally instead of asking the server.
+    pub tokenizer_file: Option<PathBuf>,
+
+    /// How failed requests are retried.
+    pub retry: RetryPolicy,
+
+    /// Which middleware layers are wrapped around the backend.
+    pub middleware: MiddlewareSettings,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MiddlewareSettings {
+    /// How many completions to cache by prompt and settings.
+    pub response_cache_size: Option<usize>,
+
+    /// How many tokenized texts to cache.
+    pub tokenize_cache_size: Option<usize>,
+
+    /// A JSON Lines file to append every request and response to.
+    pub log_file: Option<PathBuf>,
+
+    /// Whether to collect request, token and latency metrics.
+    pub metrics: bool,
+
+    /// A session file recording every request the agent makes, which can be
+    /// served back with [`ReplayLlm`](super::replay::ReplayLlm). Unlike
+    /// `log_file`, this is the outermost layer, so cached responses are
+    /// recorded as well.
+    pub record_file: Option<PathBuf>,
+}
+
+impl BackendSettings {
+    pub fn url(&self) -> &str {
+        self.url
+            .as_deref()
+            .unwrap_or(self.backend_type.default_url())
+    }
+
+    pub fn api_key(&self) -> Option<String> {
+        let name = self
+            .api_key_env
+            .as_deref()
+            .or(self.backend_type.default_api_key_env())?;
+
+        match std::env::var(name) {
+            Ok(key) => Some(key),
+            Err(_) => {
+                if self.api_key_env.is_some() {
+                    warn!("API key environment variable `{}` is not set.", name);
+                }
+                None
+            }
+        }
+    }
+
+    /// An HTTP client builder with the configured connection timeout.
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        let mut client = reqwest::Client::builder();
+
+        if let Some(timeout) = self.connect_timeout_ms {
+            client = client.connect_timeout(Duration::from_millis(timeout));
+        }
+
+        client
+    }
+
+    /// Creates a connection to the configured backend. `model` is used by
+    /// backends which need a model name outside of a completion request.
+    pub fn build(&self, model: Option<String>) -> Result<LlmWrapper, LLMError> {
+        if self.backend_type.needs_tokenizer_file() && self.tokenizer_file.is_none() {
+            return Err(LLMError::TokenizerFileRequired(self.backend_type));
+        }
+
+        let client = self.client_builder().build()?;
+        let url = self.url().trim_end_matches('/').to_string();
+
+        let llm: Box<dyn LLM> = match self.backend_type {
+            BackendType::LlamaCpp => Box::new(LlamaCppServer { url, client }),
+            BackendType::OpenAi => Box::new(
+                OpenAiCompatible::new(&url)
+                    .with_api_key(self.api_key())
+                    .with_client(client),
+            ),
+            BackendType::Ollama => Box::new(Ollama {
+                url,
+                model,
+                client,
+                ..Default::default()
+            }),
+        };
+
+        let middleware = &self.middleware;
+        let mut stack = LlmStack::from_boxed(llm);
+        let mut metrics = None;
+
+        // Innermost, so that the other layers see local tokenization too.
+        if let Some(path) = &self.tokenizer_file {
+            stack = stack.layer(LocalTokenizerLayer::new(HfTokenizer::from_file(path)?));
+        }
+
+        if middleware.metrics {
+            let layer = MetricsLayer::new();
+            metrics = Some(layer.handle());
+            stack = stack.layer(layer);
+        }
+
+        if let Some(path) = &middleware.log_file {
+            let layer = JsonlLogLayer::new(path).map_err(LLMError::FailedToOpenLogFile)?;
+            stack = stack.layer(layer);
+        }
+
+        if let Some(capacity) = middleware.response_cache_size {
+            stack = stack.layer(ResponseCacheLayer::new(capacity));
+        }
+
+        if let Some(capacity) = middleware.tokenize_cache_size {
+            stack = stack.layer(TokenizeCacheLayer::new(capacity));
+        }
+
+        if let Some(path) = &middleware.record_file {
+            let layer = JsonlLogLayer::create(path).map_err(LLMError::FailedToOpenLogFile)?;
+            stack = stack.layer(layer);
+        }
+
+        let request_timeout = self.request_timeout_ms.map(Duration::from_millis);
+        let mut llm = stack
+            .into_wrapper()
+            .with_retry_policy(self.retry.clone())
+            .with_request_timeout(request_timeout);
+
+        if let Some(metrics) = metrics {
+            llm = llm.with_metrics(metrics);
+        }
+
+        Ok(llm)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_backend_section() {
+        let settings: BackendSettings = serde_json::from_str(
+            r#"{ "type": "openai", "api_key_env": "MY_KEY", "request_timeout_ms": 500 }"#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.backend_type, BackendType::OpenAi);
+        assert_eq!(settings.url(), "http://localhost:8000");
+        assert_eq!(settings.api_key_env.as_deref(), Some("MY_KEY"));
+        assert_eq!(settings.request_timeout_ms, Some(500));
+        assert_eq!(settings.connect_timeout_ms, None);
+    }
+
+    #[test]
+    fn ollama_needs_tokenizer_file() {
+        let mut settings = BackendSettings {
+            backend_type: BackendType::Ollama,
+            ..Default::default()
+        };
+        assert!(matches!(
+            settings.build(Some(String::from("llama3"))),
+            Err(LLMError::TokenizerFileRequired(BackendType::Ollama))
+        ));
+
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        settings.tokenizer_file = Some(fixtures.join("tokenizer.json"));
+        settings.build(Some(String::from("llama3"))).unwrap();
+    }
+}
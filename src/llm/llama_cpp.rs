@@ -1,18 +1,37 @@
+use std::collections::VecDeque;
 use std::time::Instant;
 
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use json::JsonValue;
-use log::{debug, info};
+use log::{debug, info, warn};
+use reqwest::StatusCode;
 
-use super::{ChatResponse, CompletionSettings, LLMError, LlmWrapper, LogitBias, LLM};
+use super::{
+    read_json,
+    ChatResponse,
+    CompletionChunk,
+    CompletionSettings,
+    CompletionStream,
+    LLMError,
+    LlmWrapper,
+    LogitBias,
+    TokenProb,
+    TokenProbs,
+    LLM,
+};
+use crate::prompt::JinjaChatTemplate;
 
 pub struct LlamaCppServer {
     pub url: String,
+    pub client: reqwest::Client,
 }
 
 impl Default for LlamaCppServer {
     fn default() -> Self {
         Self {
             url: "http://localhost:8080".to_string(),
+            client: reqwest::Client::new(),
         }
     }
 }
@@ -22,31 +41,26 @@ impl LLM for LlamaCppServer {
     async fn validate_connection(&self) -> Result<(), LLMError> {
         let url = format!("{}/health", self.url);
 
-        loop {
-            let response = reqwest::get(&url)
-                .await
-                .map_err(|_| LLMError::FailedToAccessServer)?;
-
-            let res_text = response
-                .text()
-                .await
-                .map_err(|_| LLMError::FailedToAccessServer)?;
-
-            let res_json = json::parse(&res_text).map_err(|_| LLMError::JsonParseError {
-                json: res_text.clone(),
-            })?;
-
-            let status = res_json["status"].as_str().unwrap_or("");
-
-            match status {
-                "ok" => break,
-                "error" => return Err(LLMError::ModelNotLoaded),
-                "loading_model" => {}
-                state => return Err(LLMError::UnexpectedServerState(state.to_string())),
-            }
-        }
+        let response = self.client.get(&url).send().await?;
+        let http_status = response.status();
+        let res_text = response.text().await?;
+
+        let res_json = json::parse(&res_text).map_err(|_| LLMError::JsonParseError {
+            json: res_text.clone(),
+        })?;
+
+        let status = res_json["status"].as_str().unwrap_or("");
 
-        Ok(())
+        match status {
+            "ok" => Ok(()),
+            "error" => Err(LLMError::ModelNotLoaded),
+            "loading_model" | "loading model" => Err(LLMError::ModelLoading),
+            _ if !http_status.is_success() => Err(LLMError::HttpStatus {
+                status: http_status,
+                body: res_text,
+            }),
+            state => Err(LLMError::UnexpectedServerState(state.to_string())),
+        }
     }
 
     async fn query_completion(
@@ -54,63 +68,21 @@ impl LLM for LlamaCppServer {
         prompt: String,
         settings: &CompletionSettings,
     ) -> Result<ChatResponse, LLMError> {
-        let mut json = json::object! {
-            prompt: prompt,
-            temperature: settings.temperature,
-            top_k: settings.top_k,
-            top_p: settings.top_p,
-            min_p: settings.min_p,
-            stop: settings.stop_tokens.clone(),
-            repeat_penalty: settings.repeat_penalty,
-            repeat_last_n: settings.repeat_last_n,
-            presence_penalty: settings.presence_penalty,
-            frequency_penalty: settings.frequency_penalty,
-            logit_bias: Vec::<String>::with_capacity(0),
-            grammar: settings.grammar.clone(),
-            cache_prompt: true,
-            stream: false,
-        };
-
-        for logit_bias in &settings.logit_bias {
-            let array = match logit_bias {
-                LogitBias::Never { token } => {
-                    vec![
-                        JsonValue::Number((*token).into()),
-                        JsonValue::Boolean(false),
-                    ]
-                }
-                LogitBias::Bias { token, bias } => vec![
-                    JsonValue::Number((*token).into()),
-                    JsonValue::Number((*bias).into()),
-                ],
-            };
-
-            json["logit_bias"]
-                .push(JsonValue::Array(array))
-                .expect("Failed to push bias into JSON");
-        }
-
+        let json = completion_request(prompt, settings, false);
         debug!("LLM Request: {}", json.pretty(2));
 
         let url = format!("{}/completion", self.url);
         let time_start = Instant::now();
 
-        let response = reqwest::Client::new()
+        let response = self
+            .client
             .post(url)
             .header("Content-Type", "application/json")
             .body(json.dump())
             .send()
-            .await
-            .map_err(|_| LLMError::FailedToAccessServer)?;
+            .await?;
 
-        let res_text = response
-            .text()
-            .await
-            .map_err(|_| LLMError::FailedToAccessServer)?;
-
-        let res_json = json::parse(&res_text).map_err(|_| LLMError::JsonParseError {
-            json: res_text.clone(),
-        })?;
+        let res_json = read_json(response).await?;
 
         let elapsed = time_start.elapsed();
         let content = res_json["content"].as_str().unwrap_or("");
@@ -122,9 +94,57 @@ impl LLM for LlamaCppServer {
             prompt_token_count: res_json["tokens_evaluated"].as_usize().unwrap_or(0),
             generated_token_count: res_json["tokens_predicted"].as_usize().unwrap_or(0),
             generation_time: elapsed.as_secs_f64(),
+            token_probs: parse_token_probs(&res_json),
         })
     }
 
+    async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        let json = completion_request(prompt, settings, true);
+        debug!("LLM Request: {}", json.pretty(2));
+
+        let url = format!("{}/completion", self.url);
+        let time_start = Instant::now();
+
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(json.dump())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            return Err(LLMError::HttpStatus { status, body });
+        }
+
+        let events = EventStream {
+            bytes: response
+                .bytes_stream()
+                .map(|bytes| bytes.map(|b| b.to_vec()))
+                .boxed(),
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            text: String::new(),
+            token_probs: Vec::new(),
+            time_start,
+            done: false,
+        };
+
+        Ok(Box::pin(futures::stream::unfold(
+            events,
+            |mut events| async {
+                let chunk = events.next_chunk().await?;
+                Some((chunk, events))
+            },
+        )))
+    }
+
     async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
         let json = json::object! {
             content: text,
@@ -132,22 +152,15 @@ impl LLM for LlamaCppServer {
 
         let url = format!("{}/tokenize", self.url);
 
-        let response = reqwest::Client::new()
+        let response = self
+            .client
             .post(url)
             .header("Content-Type", "application/json")
             .body(json.dump())
             .send()
-            .await
-            .map_err(|_| LLMError::FailedToAccessServer)?;
+            .await?;
 
-        let res_text = response
-            .text()
-            .await
-            .map_err(|_| LLMError::FailedToAccessServer)?;
-
-        let res_json = json::parse(&res_text).map_err(|_| LLMError::JsonParseError {
-            json: res_text.clone(),
-        })?;
+        let res_json = read_json(response).await?;
 
         let tokens = res_json["tokens"]
             .members()
@@ -156,6 +169,240 @@ impl LLM for LlamaCppServer {
 
         Ok(tokens)
     }
+
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        let url = format!("{}/props", self.url);
+        let response = self.client.get(url).send().await?;
+
+        // Older servers have no `/props` endpoint.
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let res_json = read_json(response).await?;
+
+        let source = match res_json["chat_template"].as_str() {
+            Some(source) if !source.is_empty() => source,
+            _ => return Ok(None),
+        };
+
+        match JinjaChatTemplate::new(source) {
+            Ok(template) => Ok(Some(template.with_special_tokens(
+                res_json["bos_token"].as_str().unwrap_or(""),
+                res_json["eos_token"].as_str().unwrap_or(""),
+            ))),
+            Err(err) => {
+                warn!("Failed to compile the chat template of the model: {}", err);
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn completion_request(prompt: String, settings: &CompletionSettings, stream: bool) -> JsonValue {
+    let mut json = json::object! {
+        prompt: prompt,
+        temperature: settings.temperature,
+        top_k: settings.top_k,
+        top_p: settings.top_p,
+        min_p: settings.min_p,
+        stop: settings.all_stop_tokens(),
+        repeat_penalty: settings.repeat_penalty,
+        repeat_last_n: settings.repeat_last_n,
+        presence_penalty: settings.presence_penalty,
+        frequency_penalty: settings.frequency_penalty,
+        logit_bias: Vec::<String>::with_capacity(0),
+        grammar: settings.grammar.clone(),
+        n_predict: settings.max_tokens,
+        cache_prompt: true,
+        stream: stream,
+    };
+
+    if let Some(seed) = settings.seed {
+        json["seed"] = seed.into();
+    }
+
+    if let Some(n_keep) = settings.n_keep {
+        json["n_keep"] = n_keep.into();
+    }
+
+    if let Some(mirostat) = settings.mirostat {
+        json["mirostat"] = mirostat.into();
+    }
+
+    if let Some(mirostat_tau) = settings.mirostat_tau {
+        json["mirostat_tau"] = mirostat_tau.into();
+    }
+
+    if let Some(mirostat_eta) = settings.mirostat_eta {
+        json["mirostat_eta"] = mirostat_eta.into();
+    }
+
+    if let Some(typical_p) = settings.typical_p {
+        json["typical_p"] = typical_p.into();
+    }
+
+    if let Some(tfs_z) = settings.tfs_z {
+        json["tfs_z"] = tfs_z.into();
+    }
+
+    if let Some(n_probs) = settings.n_probs {
+        json["n_probs"] = n_probs.into();
+        // Report probabilities after the grammar and samplers are applied,
+        // rather than the raw log-probabilities.
+        json["post_sampling_probs"] = true.into();
+    }
+
+    for logit_bias in &settings.logit_bias {
+        let array = match logit_bias {
+            LogitBias::Never { token } => {
+                vec![
+                    JsonValue::Number((*token).into()),
+                    JsonValue::Boolean(false),
+                ]
+            }
+            LogitBias::Bias { token, bias } => vec![
+                JsonValue::Number((*token).into()),
+                JsonValue::Number((*bias).into()),
+            ],
+        };
+
+        json["logit_bias"]
+            .push(JsonValue::Array(array))
+            .expect("Failed to push bias into JSON");
+    }
+
+    json
+}
+
+/// Reads the top candidates for each generated token. Older servers report
+/// them as `probs`, newer ones as `top_probs` or `top_logprobs`.
+fn parse_token_probs(json: &JsonValue) -> Vec<TokenProbs> {
+    fn text(json: &JsonValue) -> String {
+        ["token", "tok_str", "content"]
+            .iter()
+            .find_map(|key| json[*key].as_str())
+            .unwrap_or("")
+            .to_string()
+    }
+
+    json["completion_probabilities"]
+        .members()
+        .map(|token| {
+            let candidates = ["probs", "top_probs", "top_logprobs"]
+                .iter()
+                .map(|key| &token[*key])
+                .find(|candidates| candidates.is_array())
+                .unwrap_or(&JsonValue::Null);
+
+            TokenProbs {
+                token: text(token),
+                top: candidates
+                    .members()
+                    .map(|candidate| TokenProb {
+                        token: text(candidate),
+                        prob: candidate["prob"]
+                            .as_f32()
+                            .or_else(|| candidate["logprob"].as_f32().map(f32::exp))
+                            .unwrap_or(0.0),
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Parses the server-sent events of a streaming completion into chunks.
+struct EventStream {
+    bytes: BoxStream<'static, reqwest::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    pending: VecDeque<Result<CompletionChunk, LLMError>>,
+    text: String,
+    token_probs: Vec<TokenProbs>,
+    time_start: Instant,
+    done: bool,
+}
+
+impl EventStream {
+    async fn next_chunk(&mut self) -> Option<Result<CompletionChunk, LLMError>> {
+        loop {
+            if let Some(chunk) = self.pending.pop_front() {
+                return Some(chunk);
+            }
+
+            if self.done {
+                return None;
+            }
+
+            if let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line = self.buffer.drain(..= end).collect::<Vec<u8>>();
+                let line = String::from_utf8_lossy(&line);
+
+                if let Some(data) = line.trim().strip_prefix("data:") {
+                    self.parse_event(data.trim());
+                }
+
+                continue;
+            }
+
+            match self.bytes.next().await {
+                Some(Ok(bytes)) => self.buffer.extend_from_slice(&bytes),
+                Some(Err(err)) => self.fail(err.into()),
+                None => self.fail(LLMError::UnexpectedEndOfStream),
+            }
+        }
+    }
+
+    fn parse_event(&mut self, data: &str) {
+        let Ok(event) = json::parse(data) else {
+            self.fail(LLMError::JsonParseError {
+                json: data.to_string(),
+            });
+            return;
+        };
+
+        if let Some(content) = event["content"].as_str() {
+            if !content.is_empty() {
+                self.text += content;
+                self.pending
+                    .push_back(Ok(CompletionChunk::Token(content.to_string())));
+            }
+        }
+
+        let stop = event["stop"].as_bool().unwrap_or(false);
+        let token_probs = parse_token_probs(&event);
+
+        // Older servers repeat the probabilities of every token in the final
+        // event, rather than only those of new tokens.
+        match stop && !token_probs.is_empty() {
+            true => self.token_probs = token_probs,
+            false => self.token_probs.extend(token_probs),
+        }
+
+        if stop {
+            let elapsed = self.time_start.elapsed();
+            info!(
+                "LLM Response: {} ({:.0} ms)",
+                self.text,
+                elapsed.as_millis()
+            );
+
+            self.done = true;
+            self.pending
+                .push_back(Ok(CompletionChunk::Done(ChatResponse {
+                    text: std::mem::take(&mut self.text),
+                    prompt_token_count: event["tokens_evaluated"].as_usize().unwrap_or(0),
+                    generated_token_count: event["tokens_predicted"].as_usize().unwrap_or(0),
+                    generation_time: elapsed.as_secs_f64(),
+                    token_probs: std::mem::take(&mut self.token_probs),
+                })));
+        }
+    }
+
+    fn fail(&mut self, err: LLMError) {
+        self.done = true;
+        self.pending.push_back(Err(err));
+    }
 }
 
 impl From<LlamaCppServer> for LlmWrapper {
@@ -163,3 +410,143 @@ impl From<LlamaCppServer> for LlmWrapper {
         Self::new(Box::new(llm))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_maps_all_settings() {
+        let settings = CompletionSettings {
+            model: Some("model.gguf".into()),
+            temperature: 0.5,
+            top_p: 0.9,
+            min_p: 0.1,
+            top_k: 20,
+            seed: Some(1234),
+            stop_tokens: vec!["\n".into(), "</s>".into()],
+            max_tokens: 64,
+            repeat_penalty: 1.5,
+            repeat_last_n: 128,
+            frequency_penalty: 0.25,
+            presence_penalty: 0.75,
+            logit_bias: vec![
+                LogitBias::Never { token: 2 },
+                LogitBias::Bias {
+                    token: 15,
+                    bias: -1.5,
+                },
+            ],
+            n_keep: Some(-1),
+            mirostat: Some(2),
+            mirostat_tau: Some(5.0),
+            mirostat_eta: Some(0.125),
+            typical_p: Some(0.95),
+            tfs_z: Some(0.5),
+            n_probs: Some(3),
+            grammar: Some("root ::= \"a\"".into()),
+            ..Default::default()
+        };
+
+        let json = completion_request("Hello".into(), &settings, false);
+        let expected = json::object! {
+            prompt: "Hello",
+            temperature: 0.5,
+            top_k: 20,
+            top_p: 0.9_f32,
+            min_p: 0.1_f32,
+            stop: ["\n", "</s>"],
+            repeat_penalty: 1.5,
+            repeat_last_n: 128,
+            presence_penalty: 0.75,
+            frequency_penalty: 0.25,
+            logit_bias: [[2, false], [15, -1.5]],
+            grammar: "root ::= \"a\"",
+            n_predict: 64,
+            cache_prompt: true,
+            stream: false,
+            seed: 1234,
+            n_keep: -1,
+            mirostat: 2,
+            mirostat_tau: 5.0,
+            mirostat_eta: 0.125,
+            typical_p: 0.95_f32,
+            tfs_z: 0.5,
+            n_probs: 3,
+            post_sampling_probs: true,
+        };
+
+        assert_eq!(json, expected);
+    }
+
+    #[tokio::test]
+    async fn streaming_completion() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/completion")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "stream": true,
+            })))
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(concat!(
+                "data: {\"content\":\"Hel\",\"stop\":false,\"completion_probabilities\":",
+                "[{\"token\":\"Hel\",\"top_probs\":[{\"token\":\"Hel\",\"prob\":0.5}]}]}\n\n",
+                "data: {\"content\":\"lo\",\"stop\":false,\"completion_probabilities\":",
+                "[{\"content\":\"lo\",\"probs\":[{\"tok_str\":\"lo\",\"prob\":0.25}]}]}\n\n",
+                "data: {\"content\":\"\",\"stop\":true,",
+                "\"tokens_evaluated\":4,\"tokens_predicted\":2}\n\n",
+            ))
+            .create_async()
+            .await;
+
+        let llm = LlamaCppServer {
+            url: server.url(),
+            ..Default::default()
+        };
+        let mut stream = llm
+            .stream_completion("Hi".into(), &CompletionSettings::default())
+            .await
+            .unwrap();
+
+        let mut tokens = Vec::new();
+        let mut response = None;
+        while let Some(chunk) = stream.next().await {
+            match chunk.unwrap() {
+                CompletionChunk::Token(token) => tokens.push(token),
+                CompletionChunk::Done(done) => response = Some(done),
+            }
+        }
+
+        mock.assert_async().await;
+        assert_eq!(tokens, vec!["Hel", "lo"]);
+
+        let response = response.unwrap();
+        assert_eq!(response.text, "Hello");
+        assert_eq!(response.prompt_token_count, 4);
+        assert_eq!(response.generated_token_count, 2);
+        assert_eq!(response.token_probs.len(), 2);
+        assert_eq!(response.confidence(), Some(0.125));
+    }
+
+    #[tokio::test]
+    async fn fetches_chat_template() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/props")
+            .with_body(r#"{ "chat_template": "{{ bos_token }}", "bos_token": "<s>" }"#)
+            .create_async()
+            .await;
+
+        let llm = LlamaCppServer {
+            url: server.url(),
+            ..Default::default()
+        };
+
+        let template = llm.chat_template().await.unwrap().unwrap();
+        assert_eq!(template.source(), "{{ bos_token }}");
+        assert_eq!(template.bos_token, "<s>");
+        assert_eq!(template.eos_token, "");
+
+        mock.assert_async().await;
+    }
+}
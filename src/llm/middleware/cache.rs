@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use log::debug;
+
+use super::Layer;
+use crate::llm::{
+    ChatResponse,
+    CompletionChunk,
+    CompletionSettings,
+    CompletionStream,
+    LLMError,
+    LLM,
+};
+use crate::prompt::JinjaChatTemplate;
+
+/// A map which forgets its oldest entries once it is full.
+struct BoundedCache<V> {
+    capacity: usize,
+    entries: HashMap<String, V>,
+    order: VecDeque<String>,
+}
+
+impl<V: Clone> BoundedCache<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<V> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.insert(key.clone(), value).is_none() {
+            self.order.push_back(key);
+        }
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Caches completions by prompt and settings. Empty responses are not
+/// cached, since they are retried.
+///
+/// Note that a cached response is returned even when sampling would have
+/// produced a different one, so this is best used with a fixed seed or a
+/// temperature of zero.
+pub struct ResponseCacheLayer {
+    capacity: usize,
+}
+
+impl ResponseCacheLayer {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity }
+    }
+}
+
+impl Layer for ResponseCacheLayer {
+    fn layer(self, inner: Box<dyn LLM>) -> Box<dyn LLM> {
+        Box::new(ResponseCache {
+            inner,
+            cache: Arc::new(Mutex::new(BoundedCache::new(self.capacity))),
+        })
+    }
+}
+
+pub struct ResponseCache {
+    inner: Box<dyn LLM>,
+    cache: Arc<Mutex<BoundedCache<ChatResponse>>>,
+}
+
+impl ResponseCache {
+    fn key(prompt: &str, settings: &CompletionSettings) -> String {
+        let settings = serde_json::to_string(settings).unwrap_or_default();
+        format!("{}\u{0}{}", settings, prompt)
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for ResponseCache {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        self.inner.validate_connection().await
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        let key = ResponseCache::key(&prompt, settings);
+        if let Some(response) = self.cache.lock().unwrap().get(&key) {
+            debug!("Using cached LLM response");
+            return Ok(response);
+        }
+
+        let response = self.inner.query_completion(prompt, settings).await?;
+        if !response.is_empty() {
+            self.cache.lock().unwrap().insert(key, response.clone());
+        }
+
+        Ok(response)
+    }
+
+    async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        let key = ResponseCache::key(&prompt, settings);
+        if let Some(response) = self.cache.lock().unwrap().get(&key) {
+            debug!("Using cached LLM response");
+            let chunks = vec![
+                Ok(CompletionChunk::Token(response.text.clone())),
+                Ok(CompletionChunk::Done(response)),
+            ];
+            return Ok(Box::pin(futures::stream::iter(chunks)));
+        }
+
+        let cache = self.cache.clone();
+        let stream = self.inner.stream_completion(prompt, settings).await?;
+
+        Ok(Box::pin(stream.inspect(move |chunk| match chunk {
+            Ok(CompletionChunk::Done(response)) if !response.is_empty() => {
+                cache.lock().unwrap().insert(key.clone(), response.clone());
+            }
+            _ => {}
+        })))
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        self.inner.tokenize(text).await
+    }
+
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        self.inner.chat_template().await
+    }
+}
+
+/// Caches tokenized text, since the same messages are often tokenized again.
+pub struct TokenizeCacheLayer {
+    capacity: usize,
+}
+
+impl TokenizeCacheLayer {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity }
+    }
+}
+
+impl Layer for TokenizeCacheLayer {
+    fn layer(self, inner: Box<dyn LLM>) -> Box<dyn LLM> {
+        Box::new(TokenizeCache {
+            inner,
+            cache: Mutex::new(BoundedCache::new(self.capacity)),
+        })
+    }
+}
+
+pub struct TokenizeCache {
+    inner: Box<dyn LLM>,
+    cache: Mutex<BoundedCache<Vec<i32>>>,
+}
+
+#[async_trait::async_trait]
+impl LLM for TokenizeCache {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        self.inner.validate_connection().await
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        self.inner.query_completion(prompt, settings).await
+    }
+
+    async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        self.inner.stream_completion(prompt, settings).await
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        if let Some(tokens) = self.cache.lock().unwrap().get(&text) {
+            return Ok(tokens);
+        }
+
+        let tokens = self.inner.tokenize(text.clone()).await?;
+        self.cache.lock().unwrap().insert(text, tokens.clone());
+
+        Ok(tokens)
+    }
+
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        self.inner.chat_template().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::middleware::LlmStack;
+    use crate::llm::mock::MockLlm;
+
+    #[tokio::test]
+    async fn caches_responses() {
+        let mock = MockLlm::new();
+        mock.push_response("a").push_response("b");
+
+        let llm = LlmStack::new(mock.clone())
+            .layer(ResponseCacheLayer::new(8))
+            .into_wrapper();
+        let settings = CompletionSettings::default();
+
+        let first = llm.query_completion("Hi".into(), &settings).await.unwrap();
+        let second = llm.query_completion("Hi".into(), &settings).await.unwrap();
+        assert_eq!(first.text, "a");
+        assert_eq!(second.text, "a");
+
+        let other = CompletionSettings {
+            temperature: 0.0,
+            ..Default::default()
+        };
+        let third = llm.query_completion("Hi".into(), &other).await.unwrap();
+        assert_eq!(third.text, "b");
+
+        assert_eq!(mock.prompts().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn skip_empty_responses() {
+        let mock = MockLlm::new();
+        mock.push_response("").push_response("").push_response("a");
+
+        let llm = LlmStack::new(mock.clone())
+            .layer(ResponseCacheLayer::new(8))
+            .into_wrapper();
+        let settings = CompletionSettings::default();
+
+        let first = llm.query_completion("Hi".into(), &settings).await.unwrap();
+        assert!(first.is_empty());
+
+        let mut stream = llm.stream_completion("Hi".into(), &settings).await.unwrap();
+        while stream.next().await.is_some() {}
+
+        let third = llm.query_completion("Hi".into(), &settings).await.unwrap();
+        assert_eq!(third.text, "a");
+        assert_eq!(mock.prompts().len(), 3);
+    }
+
+    #[test]
+    fn bounded_cache_evicts_oldest() {
+        let mut cache = BoundedCache::new(2);
+        cache.insert("a".into(), 1);
+        cache.insert("b".into(), 2);
+        cache.insert("c".into(), 3);
+
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some(2));
+        assert_eq!(cache.get("c"), Some(3));
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use super::Layer;
+use crate::llm::{ChatResponse, CompletionSettings, CompletionStream, LLMError, Tokenizer, LLM};
+use crate::prompt::JinjaChatTemplate;
+
+/// Tokenizes text locally instead of asking the server.
+///
+/// As a layer rather than a shortcut in [`LlmWrapper`](crate::llm::LlmWrapper),
+/// local tokenization is seen by the layers around it, so it is recorded and
+/// can be replayed.
+pub struct LocalTokenizerLayer {
+    tokenizer: Arc<dyn Tokenizer>,
+}
+
+impl LocalTokenizerLayer {
+    pub fn new(tokenizer: impl Tokenizer + 'static) -> Self {
+        Self {
+            tokenizer: Arc::new(tokenizer),
+        }
+    }
+}
+
+impl Layer for LocalTokenizerLayer {
+    fn layer(self, inner: Box<dyn LLM>) -> Box<dyn LLM> {
+        Box::new(LocalTokenizer {
+            inner,
+            tokenizer: self.tokenizer,
+        })
+    }
+}
+
+pub struct LocalTokenizer {
+    inner: Box<dyn LLM>,
+    tokenizer: Arc<dyn Tokenizer>,
+}
+
+#[async_trait::async_trait]
+impl LLM for LocalTokenizer {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        self.inner.validate_connection().await
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        self.inner.query_completion(prompt, settings).await
+    }
+
+    async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        self.inner.stream_completion(prompt, settings).await
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        self.tokenizer.tokenize(&text)
+    }
+
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        self.inner.chat_template().await
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::Local;
+use futures::StreamExt;
+use log::warn;
+use serde_json::{json, Value};
+
+use super::Layer;
+use crate::llm::{
+    ChatResponse,
+    CompletionChunk,
+    CompletionSettings,
+    CompletionStream,
+    LLMError,
+    LLM,
+};
+use crate::prompt::JinjaChatTemplate;
+
+/// Appends every request and its response to a JSON Lines file.
+pub struct JsonlLogLayer {
+    file: File,
+}
+
+impl JsonlLogLayer {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    /// Like [`JsonlLogLayer::new`], but replaces an existing file instead of
+    /// appending to it, so the file holds a single session.
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl Layer for JsonlLogLayer {
+    fn layer(self, inner: Box<dyn LLM>) -> Box<dyn LLM> {
+        Box::new(JsonlLogger {
+            inner,
+            file: Arc::new(Mutex::new(self.file)),
+        })
+    }
+}
+
+pub struct JsonlLogger {
+    inner: Box<dyn LLM>,
+    file: Arc<Mutex<File>>,
+}
+
+fn write_entry(file: &Mutex<File>, mut entry: Value, time_start: Instant) {
+    entry["timestamp"] = Local::now().to_rfc3339().into();
+    entry["elapsed_ms"] = (time_start.elapsed().as_millis() as u64).into();
+
+    let mut file = file.lock().unwrap();
+    if let Err(err) = writeln!(file, "{}", entry) {
+        warn!("Failed to write LLM log entry: {}", err);
+    }
+}
+
+fn completion_entry(
+    prompt: &str,
+    settings: &CompletionSettings,
+    result: Result<&ChatResponse, &LLMError>,
+) -> Value {
+    let mut entry = json!({
+        "type": "completion",
+        "prompt": prompt,
+        "settings": settings,
+    });
+
+    match result {
+        Ok(response) => {
+            entry["response"] = json!({
+                "text": response.text,
+                "prompt_token_count": response.prompt_token_count,
+                "generated_token_count": response.generated_token_count,
+                "generation_time": response.generation_time,
+                "token_probs": response.token_probs,
+            });
+        }
+        Err(err) => entry["error"] = err.to_string().into(),
+    }
+
+    entry
+}
+
+#[async_trait::async_trait]
+impl LLM for JsonlLogger {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        self.inner.validate_connection().await
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        let time_start = Instant::now();
+        let result = self.inner.query_completion(prompt.clone(), settings).await;
+
+        let entry = completion_entry(&prompt, settings, result.as_ref());
+        write_entry(&self.file, entry, time_start);
+
+        result
+    }
+
+    async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        let time_start = Instant::now();
+        let stream = match self.inner.stream_completion(prompt.clone(), settings).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                let entry = completion_entry(&prompt, settings, Err(&err));
+                write_entry(&self.file, entry, time_start);
+                return Err(err);
+            }
+        };
+
+        let file = self.file.clone();
+        let settings = settings.clone();
+
+        Ok(Box::pin(stream.inspect(move |chunk| {
+            let result = match chunk {
+                Ok(CompletionChunk::Token(_)) => return,
+                Ok(CompletionChunk::Done(response)) => Ok(response),
+                Err(err) => Err(err),
+            };
+
+            let entry = completion_entry(&prompt, &settings, result);
+            write_entry(&file, entry, time_start);
+        })))
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        let time_start = Instant::now();
+        let result = self.inner.tokenize(text.clone()).await;
+
+        let mut entry = json!({
+            "type": "tokenize",
+            "text": text,
+        });
+
+        match &result {
+            Ok(tokens) => entry["tokens"] = json!(tokens),
+            Err(err) => entry["error"] = err.to_string().into(),
+        }
+
+        write_entry(&self.file, entry, time_start);
+        result
+    }
+
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        let time_start = Instant::now();
+        let result = self.inner.chat_template().await;
+
+        let mut entry = json!({ "type": "chat_template" });
+
+        match &result {
+            Ok(template) => entry["template"] = json!(template),
+            Err(err) => entry["error"] = err.to_string().into(),
+        }
+
+        write_entry(&self.file, entry, time_start);
+        result
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use log::debug;
+
+use super::Layer;
+use crate::llm::{
+    ChatResponse,
+    CompletionChunk,
+    CompletionSettings,
+    CompletionStream,
+    LLMError,
+    LLM,
+};
+use crate::prompt::JinjaChatTemplate;
+
+/// Counts requests, failures, tokens and latency.
+#[derive(Debug, Clone, Default)]
+pub struct LlmMetrics {
+    pub completions: u64,
+    pub completion_failures: u64,
+    pub completion_latency: Duration,
+    pub prompt_tokens: u64,
+    pub generated_tokens: u64,
+    pub tokenize_requests: u64,
+    pub tokenize_failures: u64,
+    pub tokenize_latency: Duration,
+}
+
+impl LlmMetrics {
+    pub fn average_completion_latency(&self) -> Duration {
+        match self.completions {
+            0 => Duration::ZERO,
+            n => self.completion_latency / n as u32,
+        }
+    }
+
+    pub fn average_tokenize_latency(&self) -> Duration {
+        match self.tokenize_requests {
+            0 => Duration::ZERO,
+            n => self.tokenize_latency / n as u32,
+        }
+    }
+
+    fn record_completion(&mut self, result: Result<&ChatResponse, &LLMError>, latency: Duration) {
+        self.completions += 1;
+        self.completion_latency += latency;
+
+        match result {
+            Ok(response) => {
+                self.prompt_tokens += response.prompt_token_count as u64;
+                self.generated_tokens += response.generated_token_count as u64;
+            }
+            Err(_) => self.completion_failures += 1,
+        }
+
+        debug!("LLM metrics: {}", self);
+    }
+}
+
+impl fmt::Display for LlmMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} completions ({} failed, avg {:.0} ms), {} prompt tokens, {} generated tokens, {} tokenize requests ({} failed, avg {:.0} ms)",
+            self.completions,
+            self.completion_failures,
+            self.average_completion_latency().as_secs_f64() * 1000.0,
+            self.prompt_tokens,
+            self.generated_tokens,
+            self.tokenize_requests,
+            self.tokenize_failures,
+            self.average_tokenize_latency().as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// Records [`LlmMetrics`] for every request.
+#[derive(Default)]
+pub struct MetricsLayer {
+    metrics: Arc<Mutex<LlmMetrics>>,
+}
+
+impl MetricsLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a handle to read the metrics after the layer has been applied.
+    pub fn handle(&self) -> MetricsHandle {
+        MetricsHandle {
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl Layer for MetricsLayer {
+    fn layer(self, inner: Box<dyn LLM>) -> Box<dyn LLM> {
+        Box::new(Metrics {
+            inner,
+            metrics: self.metrics,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsHandle {
+    metrics: Arc<Mutex<LlmMetrics>>,
+}
+
+impl MetricsHandle {
+    pub fn snapshot(&self) -> LlmMetrics {
+        self.metrics.lock().unwrap().clone()
+    }
+}
+
+pub struct Metrics {
+    inner: Box<dyn LLM>,
+    metrics: Arc<Mutex<LlmMetrics>>,
+}
+
+#[async_trait::async_trait]
+impl LLM for Metrics {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        self.inner.validate_connection().await
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        let time_start = Instant::now();
+        let result = self.inner.query_completion(prompt, settings).await;
+
+        self.metrics
+            .lock()
+            .unwrap()
+            .record_completion(result.as_ref(), time_start.elapsed());
+
+        result
+    }
+
+    async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        let time_start = Instant::now();
+        let stream = match self.inner.stream_completion(prompt, settings).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                self.metrics
+                    .lock()
+                    .unwrap()
+                    .record_completion(Err(&err), time_start.elapsed());
+                return Err(err);
+            }
+        };
+
+        let metrics = self.metrics.clone();
+
+        Ok(Box::pin(stream.inspect(move |chunk| {
+            let result = match chunk {
+                Ok(CompletionChunk::Token(_)) => return,
+                Ok(CompletionChunk::Done(response)) => Ok(response),
+                Err(err) => Err(err),
+            };
+
+            metrics
+                .lock()
+                .unwrap()
+                .record_completion(result, time_start.elapsed());
+        })))
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        let time_start = Instant::now();
+        let result = self.inner.tokenize(text).await;
+
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.tokenize_requests += 1;
+        metrics.tokenize_latency += time_start.elapsed();
+        if result.is_err() {
+            metrics.tokenize_failures += 1;
+        }
+
+        result
+    }
+
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        self.inner.chat_template().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::llm::middleware::LlmStack;
+    use crate::llm::mock::MockLlm;
+
+    #[tokio::test]
+    async fn counts_requests_and_tokens() {
+        let mock = MockLlm::new();
+        mock.push_response("two words");
+
+        let layer = MetricsLayer::new();
+        let metrics = layer.handle();
+        let llm = LlmStack::new(mock).layer(layer).into_wrapper();
+
+        let settings = CompletionSettings::default();
+        llm.query_completion("one".into(), &settings).await.unwrap();
+        llm.query_completion("one".into(), &settings)
+            .await
+            .unwrap_err();
+        llm.tokenize("a b c".into()).await.unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.completions, 2);
+        assert_eq!(snapshot.completion_failures, 1);
+        assert_eq!(snapshot.prompt_tokens, 1);
+        assert_eq!(snapshot.generated_tokens, 2);
+        assert_eq!(snapshot.tokenize_requests, 1);
+    }
+}
@@ -0,0 +1,51 @@
+//! Composable wrappers which add behavior around any [`LLM`] backend.
+//!
+//! Each middleware implements [`LLM`] itself by forwarding to an inner
+//! backend, so layers can be stacked in any order with [`LlmStack`].
+
+use super::{LlmWrapper, LLM};
+
+mod cache;
+mod local_tokenizer;
+mod logging;
+mod metrics;
+
+pub use cache::*;
+pub use local_tokenizer::*;
+pub use logging::*;
+pub use metrics::*;
+
+/// Wraps an [`LLM`] into another [`LLM`] which adds some behavior.
+pub trait Layer {
+    fn layer(self, inner: Box<dyn LLM>) -> Box<dyn LLM>;
+}
+
+/// A backend with any number of layers applied around it. The last layer
+/// added is the outermost one, and sees every request first.
+pub struct LlmStack {
+    llm: Box<dyn LLM>,
+}
+
+impl LlmStack {
+    pub fn new(llm: impl LLM + 'static) -> Self {
+        Self { llm: Box::new(llm) }
+    }
+
+    pub fn from_boxed(llm: Box<dyn LLM>) -> Self {
+        Self { llm }
+    }
+
+    pub fn layer(self, layer: impl Layer) -> Self {
+        Self {
+            llm: layer.layer(self.llm),
+        }
+    }
+
+    pub fn into_inner(self) -> Box<dyn LLM> {
+        self.llm
+    }
+
+    pub fn into_wrapper(self) -> LlmWrapper {
+        LlmWrapper::new(self.llm)
+    }
+}
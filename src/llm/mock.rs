@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use super::{ChatResponse, CompletionSettings, LLMError, LlmWrapper, LLM};
+use crate::actions::MessageAction;
+
+/// A scripted language model for deterministic, offline tests.
+///
+/// Queued responses are replayed first, in order. Once the queue is empty,
+/// the first rule whose prompt tail matches is used. Cloning a `MockLlm` shares
+/// its state, so a test can keep a handle after passing it to an agent.
+#[derive(Clone, Default)]
+pub struct MockLlm {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    responses: VecDeque<String>,
+    rules: Vec<MockRule>,
+    prompts: Vec<String>,
+}
+
+struct MockRule {
+    prompt_tail: String,
+    response: String,
+}
+
+impl MockLlm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a response to be returned by the next completion request.
+    pub fn push_response(&self, response: &str) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state.responses.push_back(response.to_string());
+        self
+    }
+
+    /// Responds with `response` whenever the prompt ends with `prompt_tail`.
+    pub fn respond_to_tail(&self, prompt_tail: &str, response: &str) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state.rules.push(MockRule {
+            prompt_tail: prompt_tail.to_string(),
+            response: response.to_string(),
+        });
+        self
+    }
+
+    /// Responds with `response` whenever the agent is querying `action`.
+    pub fn respond_to_action(&self, action: &MessageAction, response: &str) -> &Self {
+        self.respond_to_tail(&action.as_prompt(), response)
+    }
+
+    /// Returns every prompt received so far, oldest first.
+    pub fn prompts(&self) -> Vec<String> {
+        self.state.lock().unwrap().prompts.clone()
+    }
+
+    /// Tokenizes text by splitting on whitespace and hashing each word.
+    pub fn tokenize_text(text: &str) -> Vec<i32> {
+        text.split_whitespace()
+            .map(|word| {
+                let hash = word.bytes().fold(0x811c9dc5_u32, |hash, byte| {
+                    (hash ^ byte as u32).wrapping_mul(0x01000193)
+                });
+                (hash & 0x7fffffff) as i32
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for MockLlm {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        Ok(())
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        _settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        let mut state = self.state.lock().unwrap();
+        state.prompts.push(prompt.clone());
+
+        let text = match state.responses.pop_front() {
+            Some(text) => text,
+            None => state
+                .rules
+                .iter()
+                .find(|rule| prompt.ends_with(&rule.prompt_tail))
+                .map(|rule| rule.response.clone())
+                .ok_or_else(|| {
+                    LLMError::ServerError(format!("No mock response for prompt: {}", prompt))
+                })?,
+        };
+
+        Ok(ChatResponse {
+            prompt_token_count: MockLlm::tokenize_text(&prompt).len(),
+            generated_token_count: MockLlm::tokenize_text(&text).len(),
+            generation_time: 0.0,
+            token_probs: Vec::new(),
+            text,
+        })
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        Ok(MockLlm::tokenize_text(&text))
+    }
+}
+
+impl From<MockLlm> for LlmWrapper {
+    fn from(llm: MockLlm) -> Self {
+        Self::new(Box::new(llm))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn queue_before_rules() {
+        let mock = MockLlm::new();
+        mock.push_response("first")
+            .respond_to_tail("ANSWER: ", "Yes");
+
+        let llm: LlmWrapper = mock.clone().into();
+        let settings = CompletionSettings::default();
+
+        let a = llm.query_completion("QUERY: ?\nANSWER: ".into(), &settings);
+        assert_eq!(a.await.unwrap().text, "first");
+
+        let b = llm.query_completion("QUERY: ?\nANSWER: ".into(), &settings);
+        assert_eq!(b.await.unwrap().text, "Yes");
+
+        let c = llm.query_completion("unknown".into(), &settings);
+        assert!(c.await.is_err());
+
+        assert_eq!(mock.prompts().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn deterministic_tokenizer() {
+        let llm: LlmWrapper = MockLlm::new().into();
+
+        let a = llm
+            .tokenize("the quick  brown\nfox the".into())
+            .await
+            .unwrap();
+        assert_eq!(a.len(), 5);
+        assert_eq!(a[0], a[4]);
+        assert_eq!(a, MockLlm::tokenize_text("the quick brown fox the"));
+    }
+}
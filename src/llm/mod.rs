@@ -1,25 +1,83 @@
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures::Stream;
+use json::JsonValue;
+use log::warn;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use self::middleware::{LlmMetrics, MetricsHandle};
+use crate::prompt::JinjaChatTemplate;
+
+mod backend;
+mod retry;
 mod settings;
+mod tokenizer;
 
+pub use backend::*;
+pub use retry::*;
 pub use settings::*;
+pub use tokenizer::*;
 
 pub mod llama_cpp;
+pub mod middleware;
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+pub mod replay;
 
 #[derive(Clone)]
 pub struct LlmWrapper {
     llm: Arc<Box<dyn LLM>>,
+    retry_policy: RetryPolicy,
+    request_timeout: Option<Duration>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    metrics: Option<MetricsHandle>,
 }
 
 impl LlmWrapper {
     pub fn new(llm: Box<dyn LLM>) -> Self {
-        Self { llm: Arc::new(llm) }
+        Self {
+            llm: Arc::new(llm),
+            retry_policy: RetryPolicy::default(),
+            request_timeout: None,
+            circuit_breaker: Arc::new(CircuitBreaker::default()),
+            metrics: None,
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limits how long a single attempt of a request may take. For streamed
+    /// completions, this only covers receiving the start of the response.
+    pub fn with_request_timeout(mut self, request_timeout: Option<Duration>) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: MetricsHandle) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Returns the metrics collected so far, if a [`MetricsLayer`] is used.
+    pub fn metrics(&self) -> Option<LlmMetrics> {
+        self.metrics.as_ref().map(|m| m.snapshot())
     }
 
     pub async fn validate_connection(&self) -> Result<(), LLMError> {
-        self.llm.validate_connection().await
+        self.with_retries(|| self.llm.validate_connection()).await
     }
 
     pub async fn query_completion(
@@ -27,11 +85,71 @@ impl LlmWrapper {
         prompt: String,
         settings: &CompletionSettings,
     ) -> Result<ChatResponse, LLMError> {
-        self.llm.query_completion(prompt, settings).await
+        self.with_retries(|| self.llm.query_completion(prompt.clone(), settings))
+            .await
+    }
+
+    pub async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        self.with_retries(|| self.llm.stream_completion(prompt.clone(), settings))
+            .await
     }
 
     pub async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
-        self.llm.tokenize(text).await
+        self.with_retries(|| self.llm.tokenize(text.clone())).await
+    }
+
+    pub async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        self.with_retries(|| self.llm.chat_template()).await
+    }
+
+    async fn with_retries<T, F, Fut>(&self, request: F) -> Result<T, LLMError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, LLMError>>,
+    {
+        if let Some(retry_after) = self.circuit_breaker.check() {
+            return Err(LLMError::CircuitOpen { retry_after });
+        }
+
+        let mut retry = 0;
+        loop {
+            let result = match self.request_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, request())
+                    .await
+                    .unwrap_or(Err(LLMError::Timeout(timeout))),
+                None => request().await,
+            };
+
+            match result {
+                Ok(value) => {
+                    self.circuit_breaker.record_success();
+                    return Ok(value);
+                }
+                Err(err) if err.is_transient() && retry < self.retry_policy.max_retries => {
+                    let delay = self.retry_policy.backoff(retry);
+                    warn!(
+                        "LLM request failed: {}. Retrying in {:.1}s ({}/{})",
+                        err,
+                        delay.as_secs_f64(),
+                        retry + 1,
+                        self.retry_policy.max_retries
+                    );
+
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                Err(err) => {
+                    if err.is_transient() {
+                        self.circuit_breaker.record_failure(&self.retry_policy);
+                    }
+                    return Err(err);
+                }
+            }
+        }
     }
 }
 
@@ -45,28 +163,109 @@ pub trait LLM: Send + Sync {
         settings: &CompletionSettings,
     ) -> Result<ChatResponse, LLMError>;
 
+    /// Generates a completion token by token. The stream ends with a single
+    /// [`CompletionChunk::Done`] carrying the full response. Dropping the
+    /// stream early aborts the generation.
+    ///
+    /// Backends without streaming support yield the whole response at once.
+    async fn stream_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<CompletionStream, LLMError> {
+        let response = self.query_completion(prompt, settings).await?;
+        let chunks = vec![
+            Ok(CompletionChunk::Token(response.text.clone())),
+            Ok(CompletionChunk::Done(response)),
+        ];
+
+        Ok(Box::pin(futures::stream::iter(chunks)))
+    }
+
     async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError>;
+
+    /// Returns the chat template embedded in the loaded model, if the backend
+    /// exposes one.
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        Ok(None)
+    }
 }
 
+pub type CompletionStream = Pin<Box<dyn Stream<Item = Result<CompletionChunk, LLMError>> + Send>>;
+
 #[derive(Debug)]
+pub enum CompletionChunk {
+    Token(String),
+    Done(ChatResponse),
+}
+
+#[derive(Debug, Clone)]
 pub struct ChatResponse {
     pub text: String,
     pub prompt_token_count: usize,
     pub generated_token_count: usize,
     pub generation_time: f64,
+    /// The most likely candidates for each generated token. Only filled in
+    /// when `n_probs` is set and the backend supports it.
+    pub token_probs: Vec<TokenProbs>,
 }
 
 impl ChatResponse {
     pub fn is_empty(&self) -> bool {
         self.text.is_empty()
     }
+
+    /// The probability of the generated tokens, or `None` if a token's
+    /// probability is unknown.
+    pub fn confidence(&self) -> Option<f32> {
+        if self.token_probs.is_empty() {
+            return None;
+        }
+
+        self.token_probs.iter().map(|token| token.prob()).product()
+    }
+}
+
+/// A generated token, along with the candidates the model considered.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenProbs {
+    pub token: String,
+    /// The most likely candidates, from most to least likely.
+    pub top: Vec<TokenProb>,
+}
+
+impl TokenProbs {
+    /// The probability of the token which was generated, if it is among the
+    /// top candidates.
+    pub fn prob(&self) -> Option<f32> {
+        self.top
+            .iter()
+            .find(|candidate| candidate.token == self.token)
+            .map(|candidate| candidate.prob)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenProb {
+    pub token: String,
+    pub prob: f32,
 }
 
 #[derive(Debug, Error)]
 #[allow(clippy::enum_variant_names)]
 pub enum LLMError {
-    #[error("Failed to access server")]
-    FailedToAccessServer,
+    #[error("Failed to access server: {0}")]
+    FailedToAccessServer(#[from] reqwest::Error),
+    #[error("Server responded with status {status}: {body}")]
+    HttpStatus { status: StatusCode, body: String },
+    #[error("Request timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("Too many failed requests, retrying in {retry_after:?}")]
+    CircuitOpen { retry_after: Duration },
+    #[error("The language model is still loading")]
+    ModelLoading,
+    #[error("The language model returned an empty response")]
+    EmptyResponse,
     #[error("Model list is empty")]
     EmptyModelList,
     #[error("Failed to parse JSON response:\n===\n{json}\n===")]
@@ -77,6 +276,201 @@ pub enum LLMError {
     ModelNotLoaded,
     #[error("Server health returned within an unexpected state: {0}")]
     UnexpectedServerState(String),
+    #[error("Server returned an error: {0}")]
+    ServerError(String),
+    #[error("Response stream ended before the completion was done")]
+    UnexpectedEndOfStream,
+    #[error("No model was specified for the request")]
+    ModelNotSpecified,
+    #[error("Server does not have the model `{0}`")]
+    ModelNotFound(String),
+    #[error("Server does not support tokenization")]
+    TokenizeUnsupported,
+    #[error("The {0:?} backend needs a `tokenizer_file` to count tokens")]
+    TokenizerFileRequired(BackendType),
+    #[error("Failed to load tokenizer: {0}")]
+    FailedToLoadTokenizer(String),
+    #[error("Failed to tokenize text: {0}")]
+    FailedToTokenize(String),
+    #[error("Replayed request differs from the recorded one in `{field}`:\n=== recorded\n{recorded}\n=== actual\n{actual}\n===")]
+    ReplayDivergence {
+        field: String,
+        recorded: String,
+        actual: String,
+    },
+    #[error("Every recorded request has been replayed")]
+    ReplayExhausted,
+    #[error("Failed to open LLM log file: {0}")]
+    FailedToOpenLogFile(std::io::Error),
     #[error("Failed to load grammar file at: {0}")]
     FailedToLoadGrammar(#[from] std::io::Error),
 }
+
+impl LLMError {
+    /// Whether the request may succeed if it is sent again later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            LLMError::FailedToAccessServer(err) => !err.is_builder() && !err.is_decode(),
+            LLMError::HttpStatus { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            LLMError::Timeout(_) | LLMError::ModelLoading => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the agent can keep running after this error, once the server
+    /// has recovered. An empty response is asked for again on the next
+    /// update.
+    pub fn is_recoverable(&self) -> bool {
+        self.is_transient()
+            || matches!(self, LLMError::CircuitOpen { .. } | LLMError::EmptyResponse)
+    }
+
+    /// How long to wait before sending another request after this error.
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            LLMError::CircuitOpen { retry_after } => *retry_after,
+            _ => Duration::from_secs(1),
+        }
+    }
+}
+
+/// Reads a JSON response body, failing if the server responded with an error
+/// status.
+pub(crate) async fn read_json(response: reqwest::Response) -> Result<JsonValue, LLMError> {
+    let status = response.status();
+    let res_text = response.text().await?;
+
+    if !status.is_success() {
+        return Err(LLMError::HttpStatus {
+            status,
+            body: res_text,
+        });
+    }
+
+    json::parse(&res_text).map_err(|_| LLMError::JsonParseError { json: res_text })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// Fails with the given status a number of times before tokenizing.
+    struct FlakyLlm {
+        failures: AtomicU32,
+        status: StatusCode,
+    }
+
+    #[async_trait::async_trait]
+    impl LLM for FlakyLlm {
+        async fn validate_connection(&self) -> Result<(), LLMError> {
+            Ok(())
+        }
+
+        async fn query_completion(
+            &self,
+            _prompt: String,
+            _settings: &CompletionSettings,
+        ) -> Result<ChatResponse, LLMError> {
+            Err(LLMError::EmptyResponse)
+        }
+
+        async fn tokenize(&self, _text: String) -> Result<Vec<i32>, LLMError> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(LLMError::HttpStatus {
+                    status: self.status,
+                    body: String::new(),
+                });
+            }
+
+            Ok(vec![1, 2, 3])
+        }
+    }
+
+    fn wrapper(failures: u32, status: StatusCode) -> LlmWrapper {
+        let llm = FlakyLlm {
+            failures: AtomicU32::new(failures),
+            status,
+        };
+
+        LlmWrapper::new(Box::new(llm)).with_retry_policy(RetryPolicy {
+            max_retries: 2,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+            failure_threshold: 2,
+            circuit_cooldown_ms: 60_000,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let llm = wrapper(2, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(llm.tokenize("a".into()).await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let llm = wrapper(1, StatusCode::BAD_REQUEST);
+        let result = llm.tokenize("a".into()).await;
+
+        assert!(matches!(
+            result,
+            Err(LLMError::HttpStatus { status, .. }) if status == StatusCode::BAD_REQUEST
+        ));
+    }
+
+    #[tokio::test]
+    async fn opens_circuit_after_repeated_failures() {
+        let llm = wrapper(100, StatusCode::BAD_GATEWAY);
+
+        for _ in 0 .. 2 {
+            let result = llm.tokenize("a".into()).await;
+            assert!(matches!(result, Err(LLMError::HttpStatus { .. })));
+        }
+
+        let result = llm.tokenize("a".into()).await;
+        assert!(matches!(result, Err(LLMError::CircuitOpen { .. })));
+    }
+
+    #[tokio::test]
+    async fn times_out_slow_requests() {
+        struct SlowLlm;
+
+        #[async_trait::async_trait]
+        impl LLM for SlowLlm {
+            async fn validate_connection(&self) -> Result<(), LLMError> {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(())
+            }
+
+            async fn query_completion(
+                &self,
+                _prompt: String,
+                _settings: &CompletionSettings,
+            ) -> Result<ChatResponse, LLMError> {
+                Err(LLMError::EmptyResponse)
+            }
+
+            async fn tokenize(&self, _text: String) -> Result<Vec<i32>, LLMError> {
+                Ok(Vec::new())
+            }
+        }
+
+        let llm = LlmWrapper::new(Box::new(SlowLlm))
+            .with_retry_policy(RetryPolicy {
+                max_retries: 0,
+                ..Default::default()
+            })
+            .with_request_timeout(Some(Duration::from_millis(10)));
+
+        let result = llm.validate_connection().await;
+        assert!(matches!(result, Err(LLMError::Timeout(_))));
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::Instant;
+
+use json::JsonValue;
+use log::{debug, info};
+
+use super::{read_json, ChatResponse, CompletionSettings, LLMError, LlmWrapper, LLM};
+
+/// A local Ollama server.
+///
+/// Prompts are sent in raw mode, so the message prefixes and suffixes from
+/// [`CompletionSettings`] are used instead of the model's own template.
+pub struct Ollama {
+    pub url: String,
+
+    /// The model used when [`CompletionSettings::model`] is not set, which
+    /// must be installed on the server.
+    pub model: Option<String>,
+
+    /// The value of the `format` field, such as `"json"` or a JSON schema.
+    /// Ollama does not support GBNF grammars.
+    pub format: Option<JsonValue>,
+
+    pub client: reqwest::Client,
+}
+
+impl Default for Ollama {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:11434".to_string(),
+            model: None,
+            format: None,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Ollama {
+    fn build_request(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<JsonValue, LLMError> {
+        let model = settings
+            .model
+            .as_ref()
+            .or(self.model.as_ref())
+            .ok_or(LLMError::ModelNotSpecified)?;
+
+        let mut json = json::object! {
+            model: model.as_str(),
+            prompt: prompt,
+            raw: true,
+            stream: false,
+            options: {
+                temperature: settings.temperature,
+                top_k: settings.top_k,
+                top_p: settings.top_p,
+                min_p: settings.min_p,
+                stop: settings.all_stop_tokens(),
+                num_predict: settings.max_tokens,
+                repeat_penalty: settings.repeat_penalty,
+                repeat_last_n: settings.repeat_last_n,
+                presence_penalty: settings.presence_penalty,
+                frequency_penalty: settings.frequency_penalty,
+            },
+        };
+
+        if let Some(seed) = settings.seed {
+            json["options"]["seed"] = seed.into();
+        }
+
+        if let Some(n_keep) = settings.n_keep {
+            json["options"]["num_keep"] = n_keep.into();
+        }
+
+        if let Some(mirostat) = settings.mirostat {
+            json["options"]["mirostat"] = mirostat.into();
+        }
+
+        if let Some(mirostat_tau) = settings.mirostat_tau {
+            json["options"]["mirostat_tau"] = mirostat_tau.into();
+        }
+
+        if let Some(mirostat_eta) = settings.mirostat_eta {
+            json["options"]["mirostat_eta"] = mirostat_eta.into();
+        }
+
+        if let Some(typical_p) = settings.typical_p {
+            json["options"]["typical_p"] = typical_p.into();
+        }
+
+        if let Some(tfs_z) = settings.tfs_z {
+            json["options"]["tfs_z"] = tfs_z.into();
+        }
+
+        if let Some(format) = &self.format {
+            json["format"] = format.clone();
+        }
+
+        if settings.grammar.is_some() {
+            debug!("Ollama does not support grammars, ignoring.");
+        }
+
+        Ok(json)
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for Ollama {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        let url = format!("{}/api/tags", self.url);
+
+        let response = self.client.get(&url).send().await?;
+
+        let res_json = parse_response(response).await?;
+
+        let models = &res_json["models"];
+        if models.is_empty() {
+            return Err(LLMError::EmptyModelList);
+        }
+
+        // Models are listed with their tag, which defaults to `latest`.
+        if let Some(model) = &self.model {
+            let tagged = format!("{}:latest", model);
+            let installed = models.members().any(|m| {
+                let name = m["name"].as_str().unwrap_or("");
+                name == model || name == tagged
+            });
+
+            if !installed {
+                return Err(LLMError::ModelNotFound(model.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        let json = self.build_request(prompt, settings)?;
+        debug!("LLM Request: {}", json.pretty(2));
+
+        let url = format!("{}/api/generate", self.url);
+        let time_start = Instant::now();
+
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(json.dump())
+            .send()
+            .await?;
+
+        let res_json = parse_response(response).await?;
+
+        let elapsed = time_start.elapsed();
+        let content = res_json["response"].as_str().unwrap_or("");
+
+        info!("LLM Response: {} ({:.0} ms)", content, elapsed.as_millis());
+
+        Ok(ChatResponse {
+            text: content.to_string(),
+            prompt_token_count: res_json["prompt_eval_count"].as_usize().unwrap_or(0),
+            generated_token_count: res_json["eval_count"].as_usize().unwrap_or(0),
+            generation_time: elapsed.as_secs_f64(),
+            token_probs: Vec::new(),
+        })
+    }
+
+    /// Ollama has no tokenization endpoint, so tokens are counted with a
+    /// local `tokenizer_file` instead.
+    async fn tokenize(&self, _text: String) -> Result<Vec<i32>, LLMError> {
+        Err(LLMError::TokenizeUnsupported)
+    }
+}
+
+async fn parse_response(response: reqwest::Response) -> Result<JsonValue, LLMError> {
+    let res_json = read_json(response).await?;
+
+    if let Some(error) = res_json["error"].as_str() {
+        return Err(LLMError::ServerError(error.to_string()));
+    }
+
+    Ok(res_json)
+}
+
+impl From<Ollama> for LlmWrapper {
+    fn from(llm: Ollama) -> Self {
+        Self::new(Box::new(llm))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn generate_request() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/api/generate")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "llama2:13b",
+                "prompt": "### user\nHi\n",
+                "raw": true,
+                "stream": false,
+                "options": { "num_predict": 32, "seed": 7, "stop": ["\n"] },
+            })))
+            .with_body(
+                r#"{
+                    "model": "llama2:13b",
+                    "response": "Hello!",
+                    "done": true,
+                    "prompt_eval_count": 5,
+                    "eval_count": 3
+                }"#,
+            )
+            .create_async()
+            .await;
+
+        let llm = Ollama {
+            url: server.url(),
+            ..Default::default()
+        };
+        let settings = CompletionSettings {
+            model: Some("llama2:13b".into()),
+            seed: Some(7),
+            max_tokens: 32,
+            ..Default::default()
+        };
+
+        let response = llm
+            .query_completion("### user\nHi\n".into(), &settings)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response.text, "Hello!");
+        assert_eq!(response.prompt_token_count, 5);
+        assert_eq!(response.generated_token_count, 3);
+    }
+
+    #[tokio::test]
+    async fn empty_model_list() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/tags")
+            .with_body(r#"{ "models": [] }"#)
+            .create_async()
+            .await;
+
+        let llm = Ollama {
+            url: server.url(),
+            ..Default::default()
+        };
+
+        let result = llm.validate_connection().await;
+        assert!(matches!(result, Err(LLMError::EmptyModelList)));
+    }
+
+    #[tokio::test]
+    async fn model_not_installed() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/tags")
+            .with_body(r#"{ "models": [{ "name": "llama3:latest" }, { "name": "mistral:7b" }] }"#)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let llm = |model: &str| Ollama {
+            url: server.url(),
+            model: Some(model.to_string()),
+            ..Default::default()
+        };
+
+        llm("llama3").validate_connection().await.unwrap();
+        llm("mistral:7b").validate_connection().await.unwrap();
+
+        let result = llm("mistral").validate_connection().await;
+        assert!(matches!(result, Err(LLMError::ModelNotFound(model)) if model == "mistral"));
+    }
+
+    #[tokio::test]
+    async fn missing_model() {
+        let llm = Ollama::default();
+        let result = llm
+            .query_completion("Hi".into(), &CompletionSettings::default())
+            .await;
+
+        assert!(matches!(result, Err(LLMError::ModelNotSpecified)));
+    }
+}
@@ -0,0 +1,252 @@
+use std::time::Instant;
+
+use json::JsonValue;
+use log::{debug, info};
+use reqwest::StatusCode;
+
+use super::{read_json, ChatResponse, CompletionSettings, LLMError, LlmWrapper, LogitBias, LLM};
+
+/// A server speaking the OpenAI completions protocol, such as vLLM, LM Studio
+/// or text-generation-webui.
+///
+/// Only `/v1/completions` is used. The agent formats its prompts itself, and
+/// `/v1/chat/completions` would format them again with the server's chat
+/// template.
+pub struct OpenAiCompatible {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub client: reqwest::Client,
+}
+
+impl OpenAiCompatible {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            api_key: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn build_request(&self, prompt: String, settings: &CompletionSettings) -> JsonValue {
+        let mut json = json::object! {
+            model: settings.model.clone().unwrap_or_default(),
+            temperature: settings.temperature,
+            top_p: settings.top_p,
+            stop: settings.all_stop_tokens(),
+            prompt: prompt,
+            max_tokens: settings.max_tokens,
+            frequency_penalty: settings.frequency_penalty,
+            presence_penalty: settings.presence_penalty,
+            logit_bias: JsonValue::new_object(),
+            stream: false,
+        };
+
+        if let Some(seed) = settings.seed {
+            json["seed"] = seed.into();
+        }
+
+        for logit_bias in &settings.logit_bias {
+            let (token, bias) = match logit_bias {
+                LogitBias::Never { token } => (token, -100.0),
+                LogitBias::Bias { token, bias } => (token, *bias),
+            };
+
+            json["logit_bias"][token.to_string()] = bias.into();
+        }
+
+        json
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .post(format!("{}{}", self.url, path))
+            .header("Content-Type", "application/json");
+
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.get(format!("{}{}", self.url, path));
+
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for OpenAiCompatible {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        let response = self.get("/v1/models").send().await?;
+
+        let res_json = parse_response(response).await?;
+
+        if res_json["data"].is_empty() {
+            return Err(LLMError::EmptyModelList);
+        }
+
+        Ok(())
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        let json = self.build_request(prompt, settings);
+        debug!("LLM Request: {}", json.pretty(2));
+
+        let time_start = Instant::now();
+
+        let response = self
+            .post("/v1/completions")
+            .body(json.dump())
+            .send()
+            .await?;
+
+        let res_json = parse_response(response).await?;
+
+        let elapsed = time_start.elapsed();
+        let content = res_json["choices"][0]["text"].as_str().unwrap_or("");
+
+        info!("LLM Response: {} ({:.0} ms)", content, elapsed.as_millis());
+
+        Ok(ChatResponse {
+            text: content.to_string(),
+            prompt_token_count: res_json["usage"]["prompt_tokens"].as_usize().unwrap_or(0),
+            generated_token_count: res_json["usage"]["completion_tokens"]
+                .as_usize()
+                .unwrap_or(0),
+            generation_time: elapsed.as_secs_f64(),
+            token_probs: Vec::new(),
+        })
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        // Tokenization is not part of the OpenAI protocol. vLLM and llama.cpp
+        // both expose it at `/tokenize`, but disagree on the field name.
+        let json = json::object! {
+            prompt: text.clone(),
+            content: text,
+        };
+
+        let response = self.post("/tokenize").body(json.dump()).send().await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(LLMError::TokenizeUnsupported);
+        }
+
+        let res_json = parse_response(response).await?;
+
+        let tokens = res_json["tokens"]
+            .members()
+            .map(|t| t.as_i32().unwrap_or(0))
+            .collect::<Vec<i32>>();
+
+        Ok(tokens)
+    }
+}
+
+async fn parse_response(response: reqwest::Response) -> Result<JsonValue, LLMError> {
+    let res_json = read_json(response).await?;
+
+    if !res_json["error"].is_null() {
+        let message = match res_json["error"]["message"].as_str() {
+            Some(message) => message.to_string(),
+            None => res_json["error"].to_string(),
+        };
+        return Err(LLMError::ServerError(message));
+    }
+
+    Ok(res_json)
+}
+
+impl From<OpenAiCompatible> for LlmWrapper {
+    fn from(llm: OpenAiCompatible) -> Self {
+        Self::new(Box::new(llm))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn completion_request() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/completions")
+            .match_header("authorization", "Bearer secret")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "test-model",
+                "prompt": "Hello",
+                "max_tokens": 16,
+                "seed": 42,
+                "stop": ["\n"],
+                "logit_bias": { "7": -100, "9": 2.5 },
+            })))
+            .with_body(
+                r#"{
+                    "choices": [{ "text": " world", "index": 0 }],
+                    "usage": { "prompt_tokens": 1, "completion_tokens": 2 }
+                }"#,
+            )
+            .create_async()
+            .await;
+
+        let llm = OpenAiCompatible::new(&server.url()).with_api_key(Some("secret".into()));
+        let settings = CompletionSettings {
+            model: Some("test-model".into()),
+            seed: Some(42),
+            max_tokens: 16,
+            logit_bias: vec![
+                LogitBias::Never { token: 7 },
+                LogitBias::Bias {
+                    token: 9,
+                    bias: 2.5,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let response = llm
+            .query_completion("Hello".into(), &settings)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response.text, " world");
+        assert_eq!(response.prompt_token_count, 1);
+        assert_eq!(response.generated_token_count, 2);
+    }
+
+    #[tokio::test]
+    async fn empty_model_list() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/v1/models")
+            .with_body(r#"{ "object": "list", "data": [] }"#)
+            .create_async()
+            .await;
+
+        let llm = OpenAiCompatible::new(&server.url());
+        let result = llm.validate_connection().await;
+
+        assert!(matches!(result, Err(LLMError::EmptyModelList)));
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+use log::warn;
+use serde_json::Value;
+
+use super::{ChatResponse, CompletionSettings, LLMError, LlmWrapper, LLM};
+use crate::prompt::JinjaChatTemplate;
+
+/// Serves back a session recorded by a
+/// [`JsonlLogLayer`](super::middleware::JsonlLogLayer), in order.
+///
+/// Every request must match the recorded one. In strict mode, a mismatch
+/// fails with [`LLMError::ReplayDivergence`]. Otherwise it is only logged,
+/// which helps when the prompt contains something like the current date.
+pub struct ReplayLlm {
+    entries: Mutex<VecDeque<Value>>,
+    strict: bool,
+}
+
+impl ReplayLlm {
+    pub fn from_file(path: &Path, strict: bool) -> Result<Self, LLMError> {
+        let contents = std::fs::read_to_string(path).map_err(LLMError::FailedToOpenLogFile)?;
+        Self::from_jsonl(&contents, strict)
+    }
+
+    pub fn from_jsonl(contents: &str, strict: bool) -> Result<Self, LLMError> {
+        let mut entries = VecDeque::new();
+
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            let entry: Value =
+                serde_json::from_str(line).map_err(|_| LLMError::JsonParseError {
+                    json: line.to_string(),
+                })?;
+
+            // Failed requests were retried, so only successes are replayed.
+            if entry.get("error").is_none() {
+                entries.push_back(entry);
+            }
+        }
+
+        Ok(Self {
+            entries: Mutex::new(entries),
+            strict,
+        })
+    }
+
+    fn next_entry(
+        &self,
+        request_type: &str,
+        expected: &[(&str, Value)],
+    ) -> Result<Value, LLMError> {
+        let entry = self
+            .entries
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(LLMError::ReplayExhausted)?;
+
+        if entry["type"] != request_type {
+            return Err(LLMError::ReplayDivergence {
+                field: "type".to_string(),
+                recorded: entry["type"].to_string(),
+                actual: request_type.to_string(),
+            });
+        }
+
+        for (field, actual) in expected {
+            if entry[field] == *actual {
+                continue;
+            }
+
+            let err = LLMError::ReplayDivergence {
+                field: field.to_string(),
+                recorded: value_to_string(&entry[field]),
+                actual: value_to_string(actual),
+            };
+
+            if self.strict {
+                return Err(err);
+            }
+            warn!("{}", err);
+        }
+
+        Ok(entry)
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[async_trait::async_trait]
+impl LLM for ReplayLlm {
+    async fn validate_connection(&self) -> Result<(), LLMError> {
+        Ok(())
+    }
+
+    async fn query_completion(
+        &self,
+        prompt: String,
+        settings: &CompletionSettings,
+    ) -> Result<ChatResponse, LLMError> {
+        let settings = serde_json::to_value(settings).unwrap_or_default();
+        let entry = self.next_entry(
+            "completion",
+            &[("prompt", prompt.into()), ("settings", settings)],
+        )?;
+
+        let response = &entry["response"];
+        Ok(ChatResponse {
+            text: response["text"].as_str().unwrap_or("").to_string(),
+            prompt_token_count: response["prompt_token_count"].as_u64().unwrap_or(0) as usize,
+            generated_token_count: response["generated_token_count"].as_u64().unwrap_or(0) as usize,
+            generation_time: response["generation_time"].as_f64().unwrap_or(0.0),
+            token_probs: serde_json::from_value(response["token_probs"].clone())
+                .unwrap_or_default(),
+        })
+    }
+
+    async fn tokenize(&self, text: String) -> Result<Vec<i32>, LLMError> {
+        let entry = self.next_entry("tokenize", &[("text", text.into())])?;
+
+        Ok(entry["tokens"]
+            .as_array()
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .map(|t| t.as_i64().unwrap_or(0) as i32)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn chat_template(&self) -> Result<Option<JinjaChatTemplate>, LLMError> {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Sessions recorded before chat templates were fetched lack this entry.
+        if entries.front().and_then(|e| e["type"].as_str()) != Some("chat_template") {
+            return Ok(None);
+        }
+
+        let entry = entries.pop_front().unwrap_or_default();
+        Ok(serde_json::from_value(entry["template"].clone()).unwrap_or_default())
+    }
+}
+
+impl From<ReplayLlm> for LlmWrapper {
+    fn from(llm: ReplayLlm) -> Self {
+        Self::new(Box::new(llm))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::llm::middleware::{JsonlLogLayer, LlmStack};
+    use crate::llm::mock::MockLlm;
+    use crate::llm::{BackendSettings, MiddlewareSettings};
+
+    #[tokio::test]
+    async fn replays_recorded_session() {
+        let path = std::env::temp_dir().join(format!("lily-replay-{}.jsonl", std::process::id()));
+
+        let mock = MockLlm::new();
+        mock.push_response("Hello there");
+
+        let settings = CompletionSettings {
+            seed: Some(3),
+            ..Default::default()
+        };
+
+        let recorder = LlmStack::new(mock)
+            .layer(JsonlLogLayer::create(&path).unwrap())
+            .into_wrapper();
+        recorder.tokenize("Hi".into()).await.unwrap();
+        recorder
+            .query_completion("Hi".into(), &settings)
+            .await
+            .unwrap();
+        drop(recorder);
+
+        let replay: LlmWrapper = ReplayLlm::from_file(&path, true).unwrap().into();
+        std::fs::remove_file(&path).unwrap();
+
+        let tokens = replay.tokenize("Hi".into()).await.unwrap();
+        assert_eq!(tokens, MockLlm::tokenize_text("Hi"));
+
+        let response = replay
+            .query_completion("Hi".into(), &settings)
+            .await
+            .unwrap();
+        assert_eq!(response.text, "Hello there");
+
+        let exhausted = replay.tokenize("Hi".into()).await;
+        assert!(matches!(exhausted, Err(LLMError::ReplayExhausted)));
+    }
+
+    #[tokio::test]
+    async fn replays_local_tokenization() {
+        let path = std::env::temp_dir().join(format!(
+            "lily-replay-tokenizer-{}.jsonl",
+            std::process::id()
+        ));
+
+        // Local tokenization never reaches the server, so none is needed.
+        let backend = BackendSettings {
+            tokenizer_file: Some(PathBuf::from(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/tokenizer.json"
+            ))),
+            middleware: MiddlewareSettings {
+                record_file: Some(path.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let recorder = backend.build(None).unwrap();
+        let tokens = recorder.tokenize("hello world".into()).await.unwrap();
+        drop(recorder);
+
+        let replay: LlmWrapper = ReplayLlm::from_file(&path, true).unwrap().into();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replay.tokenize("hello world".into()).await.unwrap(), tokens);
+    }
+
+    #[tokio::test]
+    async fn detects_divergence() {
+        let session = r#"{"type":"tokenize","text":"Hi","tokens":[1]}
+{"type":"tokenize","text":"Hi","tokens":[1]}"#;
+
+        let strict: LlmWrapper = ReplayLlm::from_jsonl(session, true).unwrap().into();
+        let result = strict.tokenize("Bye".into()).await;
+        assert!(matches!(
+            result,
+            Err(LLMError::ReplayDivergence { field, .. }) if field == "text"
+        ));
+
+        let result = strict
+            .query_completion("Hi".into(), &CompletionSettings::default())
+            .await;
+        assert!(matches!(
+            result,
+            Err(LLMError::ReplayDivergence { field, .. }) if field == "type"
+        ));
+
+        let lenient: LlmWrapper = ReplayLlm::from_jsonl(session, false).unwrap().into();
+        assert_eq!(lenient.tokenize("Bye".into()).await.unwrap(), vec![1]);
+    }
+}
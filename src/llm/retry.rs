@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Controls how [`super::LlmWrapper`] retries requests which failed with a
+/// transient error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// How many times a failed request is retried before giving up.
+    pub max_retries: u32,
+
+    /// The delay before the first retry.
+    pub initial_backoff_ms: u64,
+
+    /// The upper limit for the delay between two retries.
+    pub max_backoff_ms: u64,
+
+    /// The factor the delay is multiplied by after each retry.
+    pub backoff_multiplier: f64,
+
+    /// The fraction of each delay, between 0 and 1, which is randomized.
+    pub jitter: f64,
+
+    /// How many requests in a row must fail before the circuit breaker opens
+    /// and further requests are rejected without reaching the server.
+    pub failure_threshold: u32,
+
+    /// How long the circuit breaker stays open before trying again.
+    pub circuit_cooldown_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 8,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            backoff_multiplier: 2.0,
+            jitter: 0.25,
+            failure_threshold: 3,
+            circuit_cooldown_ms: 60_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait before the given retry, starting from zero.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let delay = self.initial_backoff_ms as f64 * self.backoff_multiplier.powi(retry as i32);
+        let delay = delay.min(self.max_backoff_ms as f64);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 - jitter * rand::thread_rng().gen::<f64>();
+
+        Duration::from_secs_f64(delay * factor / 1000.0)
+    }
+}
+
+/// Tracks consecutive failures of a backend, rejecting requests for a
+/// cooldown period once too many have failed in a row.
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    state: Mutex<CircuitState>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// Returns how long until requests are allowed again, if the circuit is
+    /// currently open. Once the cooldown has passed, a single trial request
+    /// is let through.
+    pub fn check(&self) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let open_until = state.open_until?;
+
+        let now = Instant::now();
+        if now < open_until {
+            return Some(open_until - now);
+        }
+
+        state.open_until = None;
+        None
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.open_until = None;
+    }
+
+    pub fn record_failure(&self, policy: &RetryPolicy) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+
+        if state.consecutive_failures >= policy.failure_threshold {
+            let cooldown = Duration::from_millis(policy.circuit_cooldown_ms);
+            state.open_until = Some(Instant::now() + cooldown);
+        }
+    }
+}
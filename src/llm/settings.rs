@@ -2,6 +2,8 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::prompt::ChatTemplate;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum LogitBias {
@@ -28,16 +30,43 @@ pub struct CompletionSettings {
     pub seed: Option<u64>,
     pub stop_tokens: Vec<String>,
     pub max_tokens: i32,
+    /// The size of the model's context window in tokens. Old messages are
+    /// dropped from the prompt to keep it within the window.
+    #[serde(default)]
+    pub context_size: Option<usize>,
     pub repeat_penalty: f32,
     pub repeat_last_n: i32,
     pub frequency_penalty: f32,
     pub presence_penalty: f32,
     pub logit_bias: Vec<LogitBias>,
+    #[serde(default)]
+    pub n_keep: Option<i32>,
+    #[serde(default)]
+    pub mirostat: Option<u8>,
+    #[serde(default)]
+    pub mirostat_tau: Option<f32>,
+    #[serde(default)]
+    pub mirostat_eta: Option<f32>,
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    #[serde(default)]
+    pub tfs_z: Option<f32>,
+    /// How many of the most likely candidates to return for each token.
+    #[serde(default)]
+    pub n_probs: Option<u32>,
+    #[serde(default)]
+    pub chat_template: ChatTemplate,
+    #[serde(default)]
     pub system_message_prefix: String,
+    #[serde(default)]
     pub system_message_suffix: String,
+    #[serde(default)]
     pub user_message_prefix: String,
+    #[serde(default)]
     pub user_message_suffix: String,
+    #[serde(default)]
     pub assistant_message_prefix: String,
+    #[serde(default)]
     pub assistant_message_suffix: String,
     pub grammar: Option<String>,
 }
@@ -53,11 +82,20 @@ impl Default for CompletionSettings {
             seed: None,
             stop_tokens: vec![String::from("\n")],
             max_tokens: 128,
+            context_size: None,
             repeat_penalty: 1.1,
             repeat_last_n: 64,
             frequency_penalty: 0.0,
             presence_penalty: 0.0,
             logit_bias: Vec::new(),
+            n_keep: None,
+            mirostat: None,
+            mirostat_tau: None,
+            mirostat_eta: None,
+            typical_p: None,
+            tfs_z: None,
+            n_probs: None,
+            chat_template: ChatTemplate::default(),
             system_message_prefix: String::from("### system\n"),
             system_message_suffix: String::from("\n"),
             user_message_prefix: String::from("### user\n"),
@@ -68,3 +106,26 @@ impl Default for CompletionSettings {
         }
     }
 }
+
+impl CompletionSettings {
+    /// Returns the configured stop tokens, followed by those of the chat
+    /// template.
+    pub fn all_stop_tokens(&self) -> Vec<String> {
+        let mut stop_tokens = self.stop_tokens.clone();
+
+        for token in self.chat_template.stop_tokens() {
+            if !stop_tokens.iter().any(|t| t == token) {
+                stop_tokens.push(token.to_string());
+            }
+        }
+
+        stop_tokens
+    }
+
+    /// The number of tokens the prompt may use, leaving room for the
+    /// response, or `None` if the context size is unknown.
+    pub fn context_budget(&self) -> Option<usize> {
+        self.context_size
+            .map(|size| size.saturating_sub(self.max_tokens.max(0) as usize))
+    }
+}
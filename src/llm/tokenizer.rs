@@ -0,0 +1,111 @@
+use std::path::Path;
+
+use super::LLMError;
+
+/// Converts text into the token ids of a model without asking the server.
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Result<Vec<i32>, LLMError>;
+}
+
+/// A tokenizer loaded from a Hugging Face `tokenizer.json` file.
+///
+/// Like the `/tokenize` endpoint of llama.cpp, no special tokens such as BOS
+/// are added, so the counts match the server.
+pub struct HfTokenizer {
+    tokenizer: tokenizers::Tokenizer,
+}
+
+impl HfTokenizer {
+    pub fn from_file(path: &Path) -> Result<Self, LLMError> {
+        let tokenizer = tokenizers::Tokenizer::from_file(path)
+            .map_err(|err| LLMError::FailedToLoadTokenizer(err.to_string()))?;
+
+        Ok(Self { tokenizer })
+    }
+}
+
+impl Tokenizer for HfTokenizer {
+    fn tokenize(&self, text: &str) -> Result<Vec<i32>, LLMError> {
+        let encoding = self
+            .tokenizer
+            .encode(text, false)
+            .map_err(|err| LLMError::FailedToTokenize(err.to_string()))?;
+
+        Ok(encoding.get_ids().iter().map(|&id| id as i32).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::llm::llama_cpp::LlamaCppServer;
+    use crate::llm::middleware::{LlmStack, LocalTokenizerLayer};
+    use crate::llm::mock::MockLlm;
+    use crate::llm::LLM;
+
+    /// A text and its token ids under the BPE merges of the toy
+    /// `tokenizer.json` fixture, worked out by hand. No special tokens are
+    /// expected.
+    #[derive(serde::Deserialize)]
+    struct TokenizerCase {
+        text: String,
+        tokens: Vec<i32>,
+    }
+
+    #[tokio::test]
+    async fn tokenizes_toy_vocabulary() {
+        let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let tokenizer = HfTokenizer::from_file(&fixtures.join("tokenizer.json")).unwrap();
+
+        let cases = std::fs::read_to_string(fixtures.join("tokenizer_cases.json")).unwrap();
+        let cases: Vec<TokenizerCase> = serde_json::from_str(&cases).unwrap();
+
+        let llm = LlmStack::new(MockLlm::new())
+            .layer(LocalTokenizerLayer::new(tokenizer))
+            .into_wrapper();
+
+        for case in cases {
+            let tokens = llm.tokenize(case.text.clone()).await.unwrap();
+            assert_eq!(tokens, case.tokens, "text: {:?}", case.text);
+        }
+    }
+
+    /// Compares a model's `tokenizer.json` with the `/tokenize` endpoint of a
+    /// llama.cpp server running the same model. The toy fixture cannot be
+    /// loaded by llama.cpp, so this needs a real model:
+    ///
+    /// ```sh
+    /// LILY_TOKENIZER_FILE=path/to/tokenizer.json LILY_LLAMA_CPP_URL=http://localhost:8080 \
+    ///     cargo test matches_llama_cpp_server -- --ignored
+    /// ```
+    #[tokio::test]
+    #[ignore = "needs a llama.cpp server and the tokenizer.json of its model"]
+    async fn matches_llama_cpp_server() {
+        let path = std::env::var("LILY_TOKENIZER_FILE").expect("LILY_TOKENIZER_FILE is not set");
+        let url = std::env::var("LILY_LLAMA_CPP_URL").expect("LILY_LLAMA_CPP_URL is not set");
+
+        let tokenizer = HfTokenizer::from_file(Path::new(&path)).unwrap();
+        let server = LlamaCppServer {
+            url,
+            ..Default::default()
+        };
+
+        let texts = [
+            "hello world",
+            " leading space",
+            "Lily: How are you today?\nAlice: Fine, thanks!",
+            "[INST] <<SYS>>\nYou are Lily.\n<</SYS>>\n\nHi [/INST]",
+            "naïve café – 日本語 🙂",
+            "   multiple   spaces\t\ttabs",
+            "",
+        ];
+
+        for text in texts {
+            let expected = server.tokenize(text.to_string()).await.unwrap();
+            let tokens = tokenizer.tokenize(text).unwrap();
+            assert_eq!(tokens, expected, "text: {:?}", text);
+        }
+    }
+}
@@ -1,12 +1,16 @@
 use std::path::PathBuf;
 use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
-use log::{error, info};
-use project_lily::agent::{Agent, AgentSettings};
+use clap::{Parser, ValueEnum};
+use log::{error, info, warn};
+use project_lily::agent::{Agent, AgentError, AgentSettings};
 use project_lily::communications::discord::{self, DiscordSettings};
-use project_lily::llm::llama_cpp::LlamaCppServer;
-use project_lily::llm::LlmWrapper;
+use project_lily::llm::replay::ReplayLlm;
+use project_lily::llm::{BackendType, LLMError};
+
+/// How often the collected LLM metrics are logged, when they are enabled.
+const METRICS_INTERVAL: Duration = Duration::from_secs(600);
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
@@ -22,6 +26,43 @@ struct Args {
 
     #[arg(long)]
     discord_log_all: bool,
+
+    #[arg(long, value_enum)]
+    llm_backend: Option<BackendArg>,
+
+    #[arg(long)]
+    llm_url: Option<String>,
+
+    /// Record every LLM request and response to a session file.
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Serve LLM responses from a recorded session file instead of a server.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// Only warn when a replayed request differs from the recorded one.
+    #[arg(long, requires = "replay")]
+    replay_lenient: bool,
+}
+
+/// The values of `--llm-backend`.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BackendArg {
+    LlamaCpp,
+    #[value(name = "openai")]
+    OpenAi,
+    Ollama,
+}
+
+impl From<BackendArg> for BackendType {
+    fn from(arg: BackendArg) -> Self {
+        match arg {
+            BackendArg::LlamaCpp => BackendType::LlamaCpp,
+            BackendArg::OpenAi => BackendType::OpenAi,
+            BackendArg::Ollama => BackendType::Ollama,
+        }
+    }
 }
 
 #[tokio::main]
@@ -46,7 +87,7 @@ async fn main() -> ExitCode {
         return ExitCode::FAILURE;
     }
 
-    let agent_settings = match AgentSettings::from_file(&args.agent) {
+    let mut agent_settings = match AgentSettings::from_file(&args.agent) {
         Ok(settings) => settings,
         Err(err) => {
             error!("{}", err);
@@ -54,8 +95,49 @@ async fn main() -> ExitCode {
         }
     };
 
-    info!("Connecting to LLM Server");
-    let llm: LlmWrapper = LlamaCppServer::default().into();
+    let llm = if let Some(path) = &args.replay {
+        info!("Replaying LLM session: {}", path.display());
+        match ReplayLlm::from_file(path, !args.replay_lenient) {
+            Ok(llm) => llm.into(),
+            Err(err) => {
+                error!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        info!("Connecting to LLM Server");
+        // Overrides are kept in the settings, since the embedder may use the
+        // same server.
+        let backend = &mut agent_settings.backend;
+        if let Some(backend_type) = args.llm_backend.map(BackendType::from) {
+            // The URL and API key of another backend would not apply.
+            if backend_type != backend.backend_type {
+                backend.url = None;
+                backend.api_key_env = None;
+            }
+            backend.backend_type = backend_type;
+        }
+        if let Some(url) = args.llm_url {
+            backend.url = Some(url);
+        }
+        if let Some(path) = args.record {
+            backend.middleware.record_file = Some(path);
+        }
+
+        info!(
+            "Using {:?} backend at {}",
+            backend.backend_type,
+            backend.url()
+        );
+        match backend.build(agent_settings.llm_options.model.clone()) {
+            Ok(llm) => llm,
+            Err(err) => {
+                error!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+
     match llm.validate_connection().await {
         Ok(_) => {}
         Err(err) => {
@@ -84,10 +166,35 @@ async fn main() -> ExitCode {
         discord::run(discord_settings, &mut agent.communication_manager);
     }
 
+    let mut metrics_logged = Instant::now();
     loop {
-        if let Err(err) = agent.update().await {
-            error!("{}", err);
-            return ExitCode::FAILURE;
+        if metrics_logged.elapsed() >= METRICS_INTERVAL {
+            log_metrics(&agent);
+            metrics_logged = Instant::now();
+        }
+
+        match agent.update().await {
+            Ok(_) => {}
+            Err(AgentError::LLMError(LLMError::ReplayExhausted)) => {
+                info!("Finished replaying LLM session");
+                log_metrics(&agent);
+                return ExitCode::SUCCESS;
+            }
+            Err(err) if err.is_recoverable() => {
+                warn!("{}", err);
+                tokio::time::sleep(err.retry_after()).await;
+            }
+            Err(err) => {
+                error!("{}", err);
+                log_metrics(&agent);
+                return ExitCode::FAILURE;
+            }
         }
     }
 }
+
+fn log_metrics(agent: &Agent) {
+    if let Some(metrics) = agent.llm.metrics() {
+        info!("LLM metrics: {}", metrics);
+    }
+}
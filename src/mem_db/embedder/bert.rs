@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use rust_bert::pipelines::sentence_embeddings::{
+    SentenceEmbeddingsBuilder,
+    SentenceEmbeddingsModel,
+    SentenceEmbeddingsModelType,
+};
+use tch::Device;
+
+use super::{Embedder, SentenceModel};
+use crate::mem_db::MemoryDBError;
+
+/// Runs a sentence embedding model locally with libtorch. The model can only
+/// be sent between threads, not shared, so it is locked while encoding.
+pub struct RustBertEmbedder {
+    model: Arc<Mutex<SentenceEmbeddingsModel>>,
+    name: String,
+    dimension: usize,
+}
+
+impl RustBertEmbedder {
+    /// Loads the model from `path`, or downloads `model` without one.
+    pub async fn new(
+        model: SentenceModel,
+        path: Option<PathBuf>,
+        gpu: bool,
+    ) -> Result<Self, MemoryDBError> {
+        let device = match gpu {
+            true => Device::cuda_if_available(),
+            false => Device::Cpu,
+        };
+
+        let name = match &path {
+            Some(path) => path.display().to_string(),
+            None => model.name().to_string(),
+        };
+
+        let model = tokio::task::spawn_blocking(move || match path {
+            Some(path) => SentenceEmbeddingsBuilder::local(path)
+                .with_device(device)
+                .create_model(),
+            None => SentenceEmbeddingsBuilder::remote(model.model_type())
+                .with_device(device)
+                .create_model(),
+        })
+        .await??;
+
+        let dimension = model.get_embedding_dim()? as usize;
+
+        Ok(Self {
+            model: Arc::new(Mutex::new(model)),
+            name,
+            dimension,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedder for RustBertEmbedder {
+    fn model_name(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, MemoryDBError> {
+        let model = self.model.clone();
+        let text = text.to_string();
+
+        // Encoding is slow, so it runs off the async executor.
+        let embeddings = tokio::task::spawn_blocking(move || {
+            let model = model.lock().unwrap_or_else(PoisonError::into_inner);
+            model.encode(&[text])
+        })
+        .await??;
+
+        Ok(embeddings.into_iter().next().unwrap_or_default())
+    }
+}
+
+impl SentenceModel {
+    fn model_type(self) -> SentenceEmbeddingsModelType {
+        match self {
+            SentenceModel::DistiluseBaseMultilingualCased => {
+                SentenceEmbeddingsModelType::DistiluseBaseMultilingualCased
+            }
+            SentenceModel::BertBaseNliMeanTokens => {
+                SentenceEmbeddingsModelType::BertBaseNliMeanTokens
+            }
+            SentenceModel::AllMiniLmL12V2 => SentenceEmbeddingsModelType::AllMiniLmL12V2,
+            SentenceModel::AllMiniLmL6V2 => SentenceEmbeddingsModelType::AllMiniLmL6V2,
+            SentenceModel::AllDistilrobertaV1 => SentenceEmbeddingsModelType::AllDistilrobertaV1,
+            SentenceModel::ParaphraseAlbertSmallV2 => {
+                SentenceEmbeddingsModelType::ParaphraseAlbertSmallV2
+            }
+            SentenceModel::SentenceT5Base => SentenceEmbeddingsModelType::SentenceT5Base,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use super::Embedder;
+use crate::mem_db::MemoryDBError;
+
+/// Embeds a text by counting its words into buckets chosen by their hash.
+/// Texts sharing words are similar, whatever they mean, but the embeddings
+/// are deterministic and need no model.
+pub struct HashingEmbedder {
+    name: String,
+    dimension: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimension: usize) -> Self {
+        Self {
+            name: format!("hashing-{}", dimension),
+            dimension,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedder for HashingEmbedder {
+    fn model_name(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, MemoryDBError> {
+        let mut embedding = vec![0.0; self.dimension];
+        if self.dimension == 0 {
+            return Ok(embedding);
+        }
+
+        let words = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty());
+
+        for word in words {
+            let hash = fnv1a(&word.to_lowercase());
+            // Another bit of the hash picks the sign, so that collisions
+            // cancel out rather than add up.
+            let sign = match hash >> 63 {
+                0 => 1.0,
+                _ => -1.0,
+            };
+            embedding[(hash % self.dimension as u64) as usize] += sign;
+        }
+
+        Ok(embedding)
+    }
+}
+
+/// A hash which, unlike the standard library's, is the same everywhere.
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mem_db::DistanceMetric;
+
+    #[tokio::test]
+    async fn similar_wording_is_similar() {
+        let embedder = HashingEmbedder::new(64);
+        let metric = DistanceMetric::Cosine;
+        let mut embeddings = Vec::new();
+        for text in ["I like apples.", "i LIKE apples", "The sky is blue."] {
+            let embedding = embedder.embed(text).await.unwrap();
+            assert_eq!(embedding.len(), 64);
+            embeddings.push(metric.prepare(&embedding));
+        }
+
+        assert_eq!(embeddings[0], embeddings[1]);
+        assert!(metric.distance(&embeddings[0], &embeddings[2]) > 0.5);
+    }
+}
@@ -0,0 +1,219 @@
+use json::JsonValue;
+use log::warn;
+use reqwest::StatusCode;
+
+use super::Embedder;
+use crate::llm::{read_json, LLMError, RetryPolicy};
+use crate::mem_db::MemoryDBError;
+
+/// Requests embeddings from the `/embedding` endpoint of a llama.cpp server,
+/// which has to be started with `--embedding`.
+pub struct LlamaCppEmbedder {
+    url: String,
+    client: reqwest::Client,
+    /// How requests which failed with a transient error are retried.
+    retry: RetryPolicy,
+    name: String,
+    dimension: usize,
+}
+
+impl LlamaCppEmbedder {
+    /// Connects to the server, and embeds a probe text to find out the
+    /// dimension of its embeddings.
+    pub async fn connect(
+        url: &str,
+        client: reqwest::Client,
+        retry: RetryPolicy,
+    ) -> Result<Self, MemoryDBError> {
+        let mut embedder = Self {
+            url: url.trim_end_matches('/').to_string(),
+            client,
+            retry,
+            name: String::from("llama.cpp"),
+            dimension: 0,
+        };
+
+        embedder.dimension = embedder.embed("dimension").await?.len();
+        if let Some(model) = embedder.model_path().await? {
+            embedder.name = model;
+        }
+
+        Ok(embedder)
+    }
+
+    /// The file name of the model loaded by the server. Older servers have
+    /// no `/props` endpoint.
+    async fn model_path(&self) -> Result<Option<String>, LLMError> {
+        let url = format!("{}/props", self.url);
+        let response = self.client.get(url).send().await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let res_json = read_json(response).await?;
+        let model = res_json["model_path"]
+            .as_str()
+            .and_then(|path| path.rsplit(['/', '\\']).next())
+            .filter(|name| !name.is_empty());
+
+        Ok(model.map(|name| format!("llama.cpp/{}", name)))
+    }
+
+    async fn request_embedding(&self, text: &str) -> Result<Vec<f32>, LLMError> {
+        let url = format!("{}/embedding", self.url);
+        let body = json::object! { content: text };
+
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(body.dump())
+            .send()
+            .await?;
+
+        let res_json = read_json(response).await?;
+        parse_embedding(&res_json).ok_or_else(|| LLMError::JsonParseError {
+            json: res_json.dump(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedder for LlamaCppEmbedder {
+    fn model_name(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, MemoryDBError> {
+        let mut retry = 0;
+        loop {
+            match self.request_embedding(text).await {
+                Err(err) if err.is_transient() && retry < self.retry.max_retries => {
+                    let delay = self.retry.backoff(retry);
+                    warn!(
+                        "Embedding request failed: {}. Retrying in {:.1}s ({}/{})",
+                        err,
+                        delay.as_secs_f64(),
+                        retry + 1,
+                        self.retry.max_retries
+                    );
+
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+}
+
+/// Reads the embedding from either an object with an `embedding` array, as
+/// older servers return, or an array of such objects. Without pooling, the
+/// server returns an embedding for each token, which are averaged.
+fn parse_embedding(json: &JsonValue) -> Option<Vec<f32>> {
+    let embedding = match json {
+        JsonValue::Array(results) => &results.first()?["embedding"],
+        json => &json["embedding"],
+    };
+
+    let JsonValue::Array(values) = embedding else {
+        return None;
+    };
+
+    if !values.iter().all(JsonValue::is_array) {
+        return values.iter().map(|v| v.as_f32()).collect();
+    }
+
+    let tokens = values
+        .iter()
+        .map(|token| {
+            token
+                .members()
+                .map(|v| v.as_f32())
+                .collect::<Option<Vec<_>>>()
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let dimension = tokens.first()?.len();
+    let mut mean = vec![0.0; dimension];
+    for token in &tokens {
+        if token.len() != dimension {
+            return None;
+        }
+        for (sum, v) in mean.iter_mut().zip(token) {
+            *sum += v / tokens.len() as f32;
+        }
+    }
+
+    Some(mean)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn embeds_with_server() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/embedding")
+            .with_body(r#"[{ "index": 0, "embedding": [[0.5, 1.0, 0.0], [1.5, 0.0, 0.0]] }]"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/props")
+            .with_body(r#"{ "model_path": "/models/nomic-embed.gguf" }"#)
+            .create_async()
+            .await;
+
+        let client = reqwest::Client::new();
+        let embedder = LlamaCppEmbedder::connect(&server.url(), client, RetryPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(embedder.model_name(), "llama.cpp/nomic-embed.gguf");
+        assert_eq!(embedder.dimension(), 3);
+        assert_eq!(embedder.embed("hi").await.unwrap(), [1.0, 0.5, 0.0]);
+
+        let old = json::parse(r#"{ "embedding": [0.25, -1] }"#).unwrap();
+        assert_eq!(parse_embedding(&old), Some(vec![0.25, -1.0]));
+    }
+
+    #[tokio::test]
+    async fn retries_unavailable_server() {
+        let mut server = mockito::Server::new_async().await;
+        let unavailable = server
+            .mock("POST", "/embedding")
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/embedding")
+            .with_body(r#"{ "embedding": [0.5, 1.0] }"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/props")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let retry = RetryPolicy {
+            max_retries: 2,
+            initial_backoff_ms: 1,
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let embedder = LlamaCppEmbedder::connect(&server.url(), client, retry)
+            .await
+            .unwrap();
+
+        unavailable.assert_async().await;
+        assert_eq!(embedder.dimension(), 2);
+    }
+}
@@ -0,0 +1,129 @@
+#[cfg(feature = "rust-bert")]
+mod bert;
+mod hashing;
+mod llama_cpp;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "rust-bert")]
+pub use self::bert::*;
+pub use self::hashing::*;
+pub use self::llama_cpp::*;
+use super::MemoryDBError;
+use crate::llm::{BackendSettings, BackendType, LLMError};
+
+/// Turns text into embeddings for the vector memory.
+#[async_trait::async_trait]
+pub trait Embedder: Send + Sync {
+    /// Identifies the model, so memories embedded with another model are not
+    /// loaded.
+    fn model_name(&self) -> &str;
+
+    /// The length of every embedding.
+    fn dimension(&self) -> usize;
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, MemoryDBError>;
+}
+
+/// Which [`Embedder`] the vector memory uses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EmbedderSettings {
+    /// A sentence embedding model run locally with rust-bert, either
+    /// downloaded by name or loaded from a directory. Needs the `rust-bert`
+    /// feature.
+    RustBert {
+        #[serde(default)]
+        model: SentenceModel,
+        /// A directory with a model converted for rust-bert, used instead of
+        /// downloading `model`.
+        path: Option<PathBuf>,
+        /// Whether to run the model on a GPU when one is available.
+        #[serde(default)]
+        gpu: bool,
+    },
+    /// The `/embedding` endpoint of a llama.cpp server started with
+    /// `--embedding`. Without a `url`, the server of a llama.cpp backend is
+    /// used. Requests follow the timeouts and retry policy of the backend.
+    LlamaCpp { url: Option<String> },
+    /// Hashes the words of a text, which needs no model. Only similar
+    /// wording is found, so this is meant for tests.
+    Hashing { dimension: usize },
+}
+
+impl Default for EmbedderSettings {
+    fn default() -> Self {
+        EmbedderSettings::RustBert {
+            model: SentenceModel::default(),
+            path: None,
+            gpu: false,
+        }
+    }
+}
+
+impl EmbedderSettings {
+    /// Loads or connects to the embedding model.
+    pub async fn build(
+        &self,
+        backend: &BackendSettings,
+    ) -> Result<Box<dyn Embedder>, MemoryDBError> {
+        Ok(match self {
+            #[cfg(feature = "rust-bert")]
+            EmbedderSettings::RustBert { model, path, gpu } => {
+                Box::new(RustBertEmbedder::new(*model, path.clone(), *gpu).await?)
+            }
+            #[cfg(not(feature = "rust-bert"))]
+            EmbedderSettings::RustBert { .. } => {
+                return Err(MemoryDBError::EmbedderUnavailable("rust-bert"));
+            }
+            EmbedderSettings::LlamaCpp { url } => {
+                let url = match (url, backend.backend_type) {
+                    (Some(url), _) => url.as_str(),
+                    (None, BackendType::LlamaCpp) => backend.url(),
+                    (None, _) => BackendType::LlamaCpp.default_url(),
+                };
+
+                let mut client = backend.client_builder();
+                if let Some(timeout) = backend.request_timeout_ms {
+                    client = client.timeout(Duration::from_millis(timeout));
+                }
+                let client = client.build().map_err(LLMError::from)?;
+
+                Box::new(LlamaCppEmbedder::connect(url, client, backend.retry.clone()).await?)
+            }
+            EmbedderSettings::Hashing { dimension } => Box::new(HashingEmbedder::new(*dimension)),
+        })
+    }
+}
+
+/// The pretrained sentence embedding models rust-bert can download.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SentenceModel {
+    DistiluseBaseMultilingualCased,
+    BertBaseNliMeanTokens,
+    #[default]
+    AllMiniLmL12V2,
+    AllMiniLmL6V2,
+    AllDistilrobertaV1,
+    ParaphraseAlbertSmallV2,
+    SentenceT5Base,
+}
+
+impl SentenceModel {
+    /// The name of the model on Hugging Face.
+    pub fn name(self) -> &'static str {
+        match self {
+            SentenceModel::DistiluseBaseMultilingualCased => "distiluse-base-multilingual-cased",
+            SentenceModel::BertBaseNliMeanTokens => "bert-base-nli-mean-tokens",
+            SentenceModel::AllMiniLmL12V2 => "all-MiniLM-L12-v2",
+            SentenceModel::AllMiniLmL6V2 => "all-MiniLM-L6-v2",
+            SentenceModel::AllDistilrobertaV1 => "all-distilroberta-v1",
+            SentenceModel::ParaphraseAlbertSmallV2 => "paraphrase-albert-small-v2",
+            SentenceModel::SentenceT5Base => "sentence-t5-base",
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use super::{check_dimension, DistanceMetric, VectorIndex};
+use crate::mem_db::MemoryDBError;
+
+/// Compares the query to every vector. Exact, and fast enough for a few
+/// thousand memories.
+pub struct BruteForceIndex {
+    dimension: usize,
+    metric: DistanceMetric,
+    vectors: Vec<(usize, Vec<f32>)>,
+}
+
+impl BruteForceIndex {
+    pub fn new(dimension: usize, metric: DistanceMetric) -> Self {
+        Self {
+            dimension,
+            metric,
+            vectors: Vec::new(),
+        }
+    }
+}
+
+impl VectorIndex for BruteForceIndex {
+    fn insert(&mut self, id: usize, vector: &[f32]) -> Result<(), MemoryDBError> {
+        check_dimension(vector, self.dimension)?;
+        self.remove(id);
+        self.vectors.push((id, self.metric.prepare(vector)));
+        Ok(())
+    }
+
+    fn remove(&mut self, id: usize) -> bool {
+        match self.vectors.iter().position(|(other, _)| *other == id) {
+            Some(position) => {
+                self.vectors.swap_remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn search(
+        &self,
+        query: &[f32],
+        count: usize,
+        filter: &dyn Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)> {
+        let query = self.metric.prepare(query);
+        let mut results = self
+            .vectors
+            .iter()
+            .filter(|(id, _)| filter(*id))
+            .map(|(id, vector)| (*id, self.metric.distance(&query, vector)))
+            .collect::<Vec<_>>();
+
+        results.sort_by(|a, b| a.1.total_cmp(&b.1));
+        results.truncate(count);
+        results
+    }
+
+    fn len(&self) -> usize {
+        self.vectors.len()
+    }
+}
@@ -0,0 +1,344 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::{check_dimension, DistanceMetric, VectorIndex};
+use crate::mem_db::MemoryDBError;
+
+/// The fraction of nodes which may be removed before the graph is rebuilt.
+const MAX_DELETED_FRACTION: f32 = 0.5;
+
+/// Tunes the trade-off between speed and recall of an [`HnswIndex`].
+#[derive(Debug, Clone, Copy)]
+pub struct HnswParams {
+    /// How many neighbors a node links to on the upper layers. Nodes link to
+    /// twice as many on the bottom layer.
+    pub m: usize,
+    /// How many candidates are considered when linking a new node.
+    pub ef_construction: usize,
+    /// How many candidates are considered when searching.
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+/// A hierarchical navigable small world graph (Malkov & Yashunin, 2016): an
+/// approximate index whose searches take logarithmic time.
+///
+/// Removed vectors are only marked as deleted, and still guide searches
+/// through the graph, until more than half of the nodes are deleted and the
+/// graph is rebuilt without them.
+pub struct HnswIndex {
+    params: HnswParams,
+    dimension: usize,
+    metric: DistanceMetric,
+    nodes: Vec<Node>,
+    /// The node of each id which has not been removed.
+    ids: HashMap<usize, usize>,
+    /// The node on the highest layer, where searches start.
+    entry: Option<usize>,
+    rng: StdRng,
+}
+
+struct Node {
+    id: usize,
+    vector: Vec<f32>,
+    /// The neighbors of the node on each layer it is part of.
+    links: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl HnswIndex {
+    pub fn new(dimension: usize, metric: DistanceMetric, params: HnswParams) -> Self {
+        Self {
+            params,
+            dimension,
+            metric,
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+            entry: None,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        match layer {
+            0 => self.params.m * 2,
+            _ => self.params.m,
+        }
+    }
+
+    /// Picks the highest layer of a new node, so that each layer has about
+    /// `m` times fewer nodes than the one below.
+    fn random_level(&mut self) -> usize {
+        let factor = 1.0 / (self.params.m.max(2) as f64).ln();
+        let uniform: f64 = self.rng.gen();
+        (-(1.0 - uniform).ln() * factor).floor() as usize
+    }
+
+    fn candidate(&self, query: &[f32], node: usize) -> Candidate {
+        Candidate {
+            distance: self.metric.distance(query, &self.nodes[node].vector),
+            node,
+        }
+    }
+
+    /// Finds the `ef` nodes nearest to the query on a layer, closest first,
+    /// by greedily following links from the entry nodes.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited = entry.iter().copied().collect::<HashSet<_>>();
+        let mut candidates = BinaryHeap::new();
+        let mut nearest = BinaryHeap::new();
+
+        for &node in entry {
+            let candidate = self.candidate(query, node);
+            candidates.push(Reverse(candidate));
+            nearest.push(candidate);
+        }
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            let furthest = nearest
+                .peek()
+                .map_or(f32::INFINITY, |c: &Candidate| c.distance);
+            if nearest.len() >= ef && current.distance > furthest {
+                break;
+            }
+
+            for &neighbor in &self.nodes[current.node].links[layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let candidate = self.candidate(query, neighbor);
+                let furthest = nearest.peek().map_or(f32::INFINITY, |c| c.distance);
+                if nearest.len() < ef || candidate.distance < furthest {
+                    candidates.push(Reverse(candidate));
+                    nearest.push(candidate);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+
+        nearest.into_sorted_vec()
+    }
+
+    /// Descends the layers above `layer`, keeping only the nearest node.
+    fn descend(&self, query: &[f32], entry: usize, layer: usize) -> usize {
+        let top = self.nodes[entry].links.len() - 1;
+        let mut node = entry;
+
+        for upper in (layer + 1 ..= top).rev() {
+            node = self.search_layer(query, &[node], 1, upper)[0].node;
+        }
+
+        node
+    }
+
+    /// Keeps only the nearest links of a node which has too many.
+    fn prune(&mut self, node: usize, layer: usize) {
+        let max = self.max_links(layer);
+        if self.nodes[node].links[layer].len() <= max {
+            return;
+        }
+
+        let vector = &self.nodes[node].vector;
+        let mut links = self.nodes[node].links[layer]
+            .iter()
+            .map(|&link| Candidate {
+                distance: self.metric.distance(vector, &self.nodes[link].vector),
+                node: link,
+            })
+            .collect::<Vec<_>>();
+
+        links.sort();
+        links.truncate(max);
+        self.nodes[node].links[layer] = links.into_iter().map(|c| c.node).collect();
+    }
+
+    /// Links a prepared vector into the graph.
+    fn add(&mut self, id: usize, vector: Vec<f32>) {
+        let level = self.random_level();
+        let node = self.nodes.len();
+
+        self.nodes.push(Node {
+            id,
+            vector: vector.clone(),
+            links: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id, node);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+
+        let top = self.nodes[entry].links.len() - 1;
+        let mut entries = vec![self.descend(&vector, entry, level)];
+
+        for layer in (0 ..= level.min(top)).rev() {
+            let found = self.search_layer(&vector, &entries, self.params.ef_construction, layer);
+
+            let neighbors = found
+                .iter()
+                .take(self.params.m)
+                .map(|c| c.node)
+                .collect::<Vec<_>>();
+
+            for &neighbor in &neighbors {
+                self.nodes[neighbor].links[layer].push(node);
+                self.prune(neighbor, layer);
+            }
+
+            self.nodes[node].links[layer] = neighbors;
+            entries = found.into_iter().map(|c| c.node).collect();
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// Builds a new graph from the nodes which have not been removed, since
+    /// deleted nodes slow down searches and are never freed.
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.ids.clear();
+        self.entry = None;
+
+        for node in nodes.into_iter().filter(|node| !node.deleted) {
+            self.add(node.id, node.vector);
+        }
+    }
+}
+
+impl VectorIndex for HnswIndex {
+    fn insert(&mut self, id: usize, vector: &[f32]) -> Result<(), MemoryDBError> {
+        check_dimension(vector, self.dimension)?;
+        self.remove(id);
+
+        self.add(id, self.metric.prepare(vector));
+        Ok(())
+    }
+
+    fn remove(&mut self, id: usize) -> bool {
+        let Some(node) = self.ids.remove(&id) else {
+            return false;
+        };
+        self.nodes[node].deleted = true;
+
+        let deleted = self.nodes.len() - self.ids.len();
+        if deleted as f32 > self.nodes.len() as f32 * MAX_DELETED_FRACTION {
+            self.rebuild();
+        }
+
+        true
+    }
+
+    fn search(
+        &self,
+        query: &[f32],
+        count: usize,
+        filter: &dyn Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        if count == 0 {
+            return Vec::new();
+        }
+
+        let query = self.metric.prepare(query);
+        let entries = [self.descend(&query, entry, 0)];
+        let mut ef = self.params.ef_search.max(count);
+
+        // Widen the search until enough nodes pass the filter.
+        loop {
+            let results = self
+                .search_layer(&query, &entries, ef, 0)
+                .into_iter()
+                .filter(|c| !self.nodes[c.node].deleted && filter(self.nodes[c.node].id))
+                .take(count)
+                .map(|c| (self.nodes[c.node].id, c.distance))
+                .collect::<Vec<_>>();
+
+            if results.len() >= count || ef >= self.nodes.len() {
+                return results;
+            }
+
+            ef *= 2;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rebuild_after_most_removals() {
+        let mut index = HnswIndex::new(2, DistanceMetric::Cosine, HnswParams::default());
+        for id in 0 .. 100 {
+            let angle = id as f32 / 100.0 * std::f32::consts::PI;
+            index.insert(id, &[angle.cos(), angle.sin()]).unwrap();
+        }
+
+        for id in 0 .. 50 {
+            assert!(index.remove(id));
+        }
+        assert_eq!(index.nodes.len(), 100);
+
+        // One more deleted node tips the balance.
+        assert!(index.remove(50));
+        assert_eq!(index.nodes.len(), 49);
+        assert!(index.nodes.iter().all(|node| !node.deleted));
+
+        let nearest = index.search(&[-1.0, 0.0], 1, &|_| true);
+        assert_eq!(nearest[0].0, 99);
+        assert!(!index.remove(50));
+    }
+}
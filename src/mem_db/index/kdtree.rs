@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use kdtree::distance::squared_euclidean;
+use kdtree::KdTree;
+
+use super::{check_dimension, DistanceMetric, VectorIndex, VectorIndexType};
+use crate::mem_db::MemoryDBError;
+
+/// An exact index splitting space along one dimension at a time. With
+/// hundreds of dimensions, most of the tree has to be visited anyway.
+///
+/// The tree is searched by Euclidean distance between unit vectors, so it
+/// cannot rank by the inner product of embeddings of any length.
+pub struct KdTreeIndex {
+    dimension: usize,
+    metric: DistanceMetric,
+    tree: KdTree<f32, usize, Vec<f32>>,
+    /// The vectors by id, which are needed to remove them from the tree.
+    vectors: HashMap<usize, Vec<f32>>,
+}
+
+impl KdTreeIndex {
+    pub fn new(dimension: usize, metric: DistanceMetric) -> Result<Self, MemoryDBError> {
+        if metric == DistanceMetric::Dot {
+            return Err(MemoryDBError::UnsupportedMetric {
+                index: VectorIndexType::KdTree,
+                metric,
+            });
+        }
+
+        Ok(Self {
+            dimension,
+            metric,
+            tree: KdTree::new(dimension),
+            vectors: HashMap::new(),
+        })
+    }
+}
+
+impl VectorIndex for KdTreeIndex {
+    fn insert(&mut self, id: usize, vector: &[f32]) -> Result<(), MemoryDBError> {
+        check_dimension(vector, self.dimension)?;
+        self.remove(id);
+
+        let vector = self.metric.prepare(vector);
+        self.tree.add(vector.clone(), id)?;
+        self.vectors.insert(id, vector);
+        Ok(())
+    }
+
+    fn remove(&mut self, id: usize) -> bool {
+        match self.vectors.remove(&id) {
+            Some(vector) => self
+                .tree
+                .remove(&vector, &id)
+                .is_ok_and(|removed| removed > 0),
+            None => false,
+        }
+    }
+
+    fn search(
+        &self,
+        query: &[f32],
+        count: usize,
+        filter: &dyn Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)> {
+        let query = self.metric.prepare(query);
+        let Ok(nearest) = self.tree.iter_nearest(&query, &squared_euclidean) else {
+            return Vec::new();
+        };
+
+        nearest
+            .filter(|(_, id)| filter(**id))
+            .take(count)
+            .map(|(_, id)| (*id, self.metric.distance(&query, &self.vectors[id])))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.vectors.len()
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use super::normalize;
+
+/// How the distance between two embeddings is measured. Whatever the metric,
+/// distances convert to a similarity from 0, for opposite embeddings, to 1,
+/// for identical ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceMetric {
+    /// The angle between the embeddings, ignoring their length.
+    #[default]
+    Cosine,
+    /// The inner product of the embeddings as they are. For models which
+    /// output unit vectors, this ranks memories like `Cosine`.
+    Dot,
+    /// The Euclidean distance between the embeddings scaled to unit length.
+    L2,
+}
+
+impl DistanceMetric {
+    /// Prepares an embedding to be compared. Every metric but `Dot` scales it
+    /// to unit length.
+    pub fn prepare(self, vector: &[f32]) -> Vec<f32> {
+        match self {
+            DistanceMetric::Dot => vector.to_vec(),
+            DistanceMetric::Cosine | DistanceMetric::L2 => normalize(vector),
+        }
+    }
+
+    /// The distance between two prepared embeddings, lower being nearer.
+    pub fn distance(self, a: &[f32], b: &[f32]) -> f32 {
+        let dot = || a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+        match self {
+            DistanceMetric::Cosine => 1.0 - dot(),
+            DistanceMetric::Dot => -dot(),
+            DistanceMetric::L2 => a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+
+    /// Converts a distance to a similarity from 0 to 1. `Cosine` and `L2`
+    /// give the same similarity for the same embeddings.
+    pub fn similarity(self, distance: f32) -> f32 {
+        let similarity = match self {
+            DistanceMetric::Cosine => 1.0 - distance / 2.0,
+            // Between unit vectors, the cosine distance is half the squared
+            // Euclidean distance.
+            DistanceMetric::L2 => 1.0 - distance * distance / 4.0,
+            DistanceMetric::Dot => (1.0 - distance) / 2.0,
+        };
+        similarity.clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_similarity() {
+        let a = [3.0, 0.0];
+        let cases = [([1.0, 0.0], 1.0), ([0.0, 2.0], 0.5), ([-1.0, 0.0], 0.0)];
+
+        for metric in [DistanceMetric::Cosine, DistanceMetric::L2] {
+            for (b, expected) in cases {
+                let distance = metric.distance(&metric.prepare(&a), &metric.prepare(&b));
+                let similarity = metric.similarity(distance);
+                assert!((similarity - expected).abs() < 1e-6, "{:?} {:?}", metric, b);
+            }
+        }
+
+        // The inner product grows with the length of the embeddings, so it is
+        // clamped.
+        let metric = DistanceMetric::Dot;
+        assert_eq!(metric.similarity(metric.distance(&a, &[1.0, 0.0])), 1.0);
+        assert_eq!(metric.similarity(metric.distance(&a, &[0.0, 1.0])), 0.5);
+        assert_eq!(metric.similarity(metric.distance(&a, &[-1.0, 0.0])), 0.0);
+    }
+}
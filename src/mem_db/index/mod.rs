@@ -0,0 +1,161 @@
+mod brute;
+mod hnsw;
+mod kdtree;
+mod metric;
+
+use serde::{Deserialize, Serialize};
+
+pub use self::brute::*;
+pub use self::hnsw::*;
+pub use self::kdtree::*;
+pub use self::metric::*;
+use super::MemoryDBError;
+
+/// A nearest-neighbor index of embeddings by a [`DistanceMetric`]. Each
+/// vector is stored under an id chosen by the caller.
+pub trait VectorIndex: Send + Sync {
+    /// Adds a vector, replacing any vector stored under the same id.
+    fn insert(&mut self, id: usize, vector: &[f32]) -> Result<(), MemoryDBError>;
+
+    /// Removes a vector, returning whether there was one.
+    fn remove(&mut self, id: usize) -> bool;
+
+    /// Finds the `count` vectors nearest to the query whose ids pass the
+    /// filter, closest first, along with their distance.
+    fn search(
+        &self,
+        query: &[f32],
+        count: usize,
+        filter: &dyn Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Which [`VectorIndex`] the vector memory uses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorIndexType {
+    /// Approximate, and fast for any number of memories.
+    #[default]
+    Hnsw,
+    /// Exact, but close to a linear scan for high dimensions.
+    KdTree,
+    /// Exact, comparing the query to every memory.
+    BruteForce,
+}
+
+impl VectorIndexType {
+    pub fn build(
+        self,
+        dimension: usize,
+        metric: DistanceMetric,
+    ) -> Result<Box<dyn VectorIndex>, MemoryDBError> {
+        Ok(match self {
+            VectorIndexType::Hnsw => {
+                Box::new(HnswIndex::new(dimension, metric, HnswParams::default()))
+            }
+            VectorIndexType::KdTree => Box::new(KdTreeIndex::new(dimension, metric)?),
+            VectorIndexType::BruteForce => Box::new(BruteForceIndex::new(dimension, metric)),
+        })
+    }
+}
+
+/// Scales a vector to unit length.
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    match norm > 0.0 {
+        true => vector.iter().map(|v| v / norm).collect(),
+        false => vector.to_vec(),
+    }
+}
+
+fn check_dimension(vector: &[f32], dimension: usize) -> Result<(), MemoryDBError> {
+    match vector.len() == dimension {
+        true => Ok(()),
+        false => Err(MemoryDBError::WrongEmbeddingSize {
+            expected: dimension,
+            actual: vector.len(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn indexes_agree_with_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut random = |n: usize| {
+            (0 .. n)
+                .map(|_| rng.gen_range(-1.0 .. 1.0))
+                .collect::<Vec<f32>>()
+        };
+
+        let vectors = (0 .. 1000).map(|_| random(16)).collect::<Vec<_>>();
+        let queries = (0 .. 20).map(|_| random(16)).collect::<Vec<_>>();
+
+        // Between unit vectors, the L2 distance ranks like the cosine one.
+        let mut exact = BruteForceIndex::new(16, DistanceMetric::Cosine);
+        let mut indexes = [
+            VectorIndexType::Hnsw.build(16, DistanceMetric::Cosine),
+            VectorIndexType::KdTree.build(16, DistanceMetric::L2),
+        ]
+        .map(Result::unwrap);
+
+        for (id, vector) in vectors.iter().enumerate() {
+            exact.insert(id, vector).unwrap();
+            for index in &mut indexes {
+                index.insert(id, vector).unwrap();
+            }
+        }
+
+        // Remove every tenth vector, and only search even ids.
+        for id in (0 .. 1000).step_by(10) {
+            assert!(exact.remove(id));
+            for index in &mut indexes {
+                assert!(index.remove(id));
+                assert!(!index.remove(id));
+            }
+        }
+
+        let even = |id: usize| id % 2 == 0;
+        for index in &indexes {
+            assert_eq!(index.len(), 900);
+
+            let mut found = 0;
+            for query in &queries {
+                let expected = exact.search(query, 10, &even);
+                let actual = index.search(query, 10, &even);
+
+                assert_eq!(actual.len(), 10);
+                assert!(actual.iter().all(|(id, _)| even(*id) && id % 10 != 0));
+                assert!(actual.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+
+                found += actual
+                    .iter()
+                    .filter(|a| expected.iter().any(|e| e.0 == a.0))
+                    .count();
+            }
+
+            assert!(found >= 180, "recall {} / 200", found);
+        }
+
+        assert!(VectorIndexType::KdTree
+            .build(16, DistanceMetric::Dot)
+            .is_err());
+
+        assert!(matches!(
+            indexes[0].insert(0, &[1.0]),
+            Err(MemoryDBError::WrongEmbeddingSize { .. })
+        ));
+    }
+}
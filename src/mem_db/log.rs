@@ -2,10 +2,16 @@ use itertools::Itertools;
 use log::info;
 
 use crate::llm::CompletionSettings;
-use crate::prompt::{ChatMessage, SystemMessageSeverity};
+use crate::prompt::{ChatMessage, JinjaChatTemplate, SystemMessageSeverity};
 
 pub struct MessageLog {
     messages: Vec<ChatMessage>,
+    /// How many messages at the start of the log are never evicted: the
+    /// pre-prompt, followed by the conversation summary once there is one.
+    pinned: usize,
+    /// Memories recalled for the next response, shown after the newest
+    /// message without being part of the history.
+    recalled: Option<ChatMessage>,
 }
 
 impl MessageLog {
@@ -16,6 +22,8 @@ impl MessageLog {
                 content: "Pre-Prompt Placeholder".to_string(),
                 tokens: None,
             }],
+            pinned: 1,
+            recalled: None,
         }
     }
 
@@ -27,13 +35,95 @@ impl MessageLog {
         };
     }
 
+    /// Sets the summary of the evicted messages, kept right after the
+    /// pre-prompt.
+    pub fn set_summary(&mut self, summary: ChatMessage) {
+        match self.pinned {
+            1 => {
+                self.messages.insert(1, summary);
+                self.pinned = 2;
+            }
+            _ => self.messages[1] = summary,
+        }
+    }
+
+    /// Every message in the log, starting with the pinned ones.
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn messages_mut(&mut self) -> &mut [ChatMessage] {
+        &mut self.messages
+    }
+
+    pub fn set_recalled(&mut self, recalled: Option<ChatMessage>) {
+        self.recalled = recalled;
+    }
+
+    /// The newest messages after the pre-prompt and summary, at most `count`.
+    pub fn recent(&self, count: usize) -> &[ChatMessage] {
+        let start = self.messages.len().saturating_sub(count).max(self.pinned);
+        &self.messages[start ..]
+    }
+
     pub fn add_message(&mut self, message: ChatMessage) {
         info!("{} : {}", message.get_role(), message.get_content());
         self.messages.push(message);
     }
 
+    /// The number of tokens in the log. Messages which have not been counted
+    /// yet are treated as empty.
+    pub fn token_count(&self) -> usize {
+        self.messages
+            .iter()
+            .chain(&self.recalled)
+            .map(|message| message.get_tokens().unwrap_or(0))
+            .sum()
+    }
+
+    /// The oldest messages which have to be dropped for the log to fit
+    /// within `budget` tokens. The pre-prompt and the summary are always
+    /// kept.
+    pub fn overflow(&self, budget: usize) -> &[ChatMessage] {
+        let mut total = self.token_count();
+        let mut count = 0;
+
+        for message in &self.messages[self.pinned ..] {
+            if total <= budget {
+                break;
+            }
+
+            total -= message.get_tokens().unwrap_or(0);
+            count += 1;
+        }
+
+        &self.messages[self.pinned .. self.pinned + count]
+    }
+
+    /// Drops the `count` oldest messages after the pre-prompt and the
+    /// summary.
+    pub fn evict(&mut self, count: usize) {
+        self.messages.drain(self.pinned .. self.pinned + count);
+    }
+
     pub fn format(&self, settings: &CompletionSettings) -> String {
-        self.messages.iter().map(|l| l.format(settings)).join("")
+        self.messages
+            .iter()
+            .chain(&self.recalled)
+            .map(|l| l.format(settings))
+            .join("")
+    }
+
+    /// Renders the log with a chat template, ending with an open assistant
+    /// turn.
+    pub fn render(&self, template: &JinjaChatTemplate) -> Result<String, minijinja::Error> {
+        match &self.recalled {
+            Some(recalled) => {
+                let messages = self.messages.iter().chain([recalled]).cloned();
+                template.render(&messages.collect::<Vec<_>>(), true)
+            }
+            None => template.render(&self.messages, true),
+        }
     }
 }
 
@@ -42,3 +132,115 @@ impl Default for MessageLog {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::actions::MessageAction;
+
+    fn system_message(content: &str) -> ChatMessage {
+        ChatMessage::System {
+            severity: SystemMessageSeverity::Info,
+            content: content.to_string(),
+            tokens: None,
+        }
+    }
+
+    fn assistant_message(action: MessageAction, content: &str) -> ChatMessage {
+        ChatMessage::Assistant {
+            action,
+            content: content.to_string(),
+            tokens: None,
+        }
+    }
+
+    #[test]
+    fn evict_oldest_messages() {
+        let mut log = MessageLog::new();
+        log.update_pre_prompt(String::from("System"), 50);
+
+        for i in 0 .. 4 {
+            log.add_message(ChatMessage::Assistant {
+                action: MessageAction::Say,
+                content: format!("Message {}", i),
+                tokens: Some(20),
+            });
+        }
+
+        assert!(log.overflow(130).is_empty());
+
+        let overflow = log.overflow(100);
+        let contents = overflow.iter().map(|m| m.get_content()).collect::<Vec<_>>();
+        assert_eq!(contents, vec!["SAY: Message 0", "SAY: Message 1"]);
+        log.evict(overflow.len());
+        assert_eq!(log.token_count(), 90);
+        assert_eq!(log.messages[0].get_content(), "[INFO] System");
+
+        log.set_summary(ChatMessage::System {
+            severity: SystemMessageSeverity::Info,
+            content: String::from("Summary"),
+            tokens: Some(10),
+        });
+        log.evict(log.overflow(0).len());
+        assert_eq!(log.messages.len(), 2);
+        assert_eq!(log.token_count(), 60);
+        assert_eq!(log.messages[1].get_content(), "[INFO] Summary");
+        assert!(log.recent(3).is_empty());
+
+        log.set_recalled(Some(ChatMessage::System {
+            severity: SystemMessageSeverity::Info,
+            content: String::from("Recalled"),
+            tokens: Some(5),
+        }));
+        assert_eq!(log.token_count(), 65);
+        assert!(log
+            .format(&CompletionSettings::default())
+            .ends_with("[INFO] Recalled\n"));
+    }
+
+    #[test]
+    fn render_with_llama2_template() {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/llama-2-chat.jinja");
+        let source = std::fs::read_to_string(path).unwrap();
+        let template = JinjaChatTemplate::new(source)
+            .unwrap()
+            .with_special_tokens("<s>", "</s>");
+
+        let mut log = MessageLog::new();
+        log.update_pre_prompt(String::from("You are Lily."), 4);
+        log.add_message(assistant_message(
+            MessageAction::SituationalAnalysis,
+            "Nobody is here.",
+        ));
+        log.add_message(assistant_message(MessageAction::Say, "Hello?"));
+
+        // The agent often speaks first, which the template does not allow.
+        assert_eq!(
+            log.render(&template).unwrap(),
+            "<s>[INST] <<SYS>>\n[INFO] You are Lily.\n<</SYS>> [/INST] SITUATIONAL_ANALYSIS: Nobody is here.\nSAY: Hello? </s>"
+        );
+
+        log.set_summary(system_message("Conversation so far:\nLily woke up."));
+        log.add_message(ChatMessage::User {
+            username: String::from("Tester"),
+            channel: None,
+            content: String::from("Hi Lily!"),
+            tokens: None,
+        });
+        log.add_message(system_message("Nothing to remember."));
+        log.add_message(assistant_message(MessageAction::Say, "Hi!"));
+        log.set_recalled(Some(system_message(
+            "Recalled memories:\n- Tester likes apples.",
+        )));
+
+        assert_eq!(
+            log.render(&template).unwrap(),
+            "<s>[INST] <<SYS>>\n[INFO] You are Lily.\n<</SYS>>\n\n[INFO] Conversation so far:\nLily woke up. [/INST] SITUATIONAL_ANALYSIS: Nobody is here.\nSAY: Hello? </s>\
+             <s>[INST] Tester: Hi Lily!\n[INFO] Nothing to remember. [/INST] SAY: Hi! </s>\
+             <s>[INST] [INFO] Recalled memories:\n- Tester likes apples. [/INST]"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+
+/// How important a memory is when nothing else is known, from 0 to 1.
+pub const DEFAULT_IMPORTANCE: f32 = 0.5;
+
+/// Describes where a memory came from, and how it has been used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryMetadata {
+    pub created_at: DateTime<Utc>,
+    /// When the memory was last recalled, or created if it never was.
+    pub last_accessed_at: DateTime<Utc>,
+    /// The channel the message was received from, such as `discord`.
+    pub source: Option<String>,
+    /// The user who sent the message.
+    pub speaker: Option<String>,
+    /// The action state the agent was in when it wrote the message.
+    pub action: Option<String>,
+    /// How important the memory is, from 0 to 1.
+    pub importance: f32,
+}
+
+impl Default for MemoryMetadata {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            created_at: now,
+            last_accessed_at: now,
+            source: None,
+            speaker: None,
+            action: None,
+            importance: DEFAULT_IMPORTANCE,
+        }
+    }
+}
+
+/// Restricts which memories a search may return. Every field which is set
+/// has to match.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFilter {
+    pub source: Option<String>,
+    pub speaker: Option<String>,
+    pub action: Option<String>,
+    /// Only memories created at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only memories created before this time.
+    pub until: Option<DateTime<Utc>>,
+    pub min_importance: Option<f32>,
+}
+
+impl MemoryFilter {
+    pub fn matches(&self, metadata: &MemoryMetadata) -> bool {
+        let equals =
+            |filter: &Option<String>, value: &Option<String>| filter.is_none() || filter == value;
+
+        equals(&self.source, &metadata.source)
+            && equals(&self.speaker, &metadata.speaker)
+            && equals(&self.action, &metadata.action)
+            && self
+                .since
+                .map_or(true, |since| metadata.created_at >= since)
+            && self.until.map_or(true, |until| metadata.created_at < until)
+            && self
+                .min_importance
+                .map_or(true, |min| metadata.importance >= min)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn filter_memories() {
+        let metadata = MemoryMetadata {
+            created_at: Utc::now() - Duration::days(3),
+            source: Some(String::from("discord")),
+            speaker: Some(String::from("alice")),
+            ..Default::default()
+        };
+
+        let last_week = MemoryFilter {
+            source: Some(String::from("discord")),
+            speaker: Some(String::from("alice")),
+            since: Some(Utc::now() - Duration::weeks(1)),
+            ..Default::default()
+        };
+        assert!(last_week.matches(&metadata));
+        assert!(MemoryFilter::default().matches(&metadata));
+
+        let last_day = MemoryFilter {
+            since: Some(Utc::now() - Duration::days(1)),
+            ..last_week.clone()
+        };
+        assert!(!last_day.matches(&metadata));
+
+        let bob = MemoryFilter {
+            speaker: Some(String::from("bob")),
+            ..last_week
+        };
+        assert!(!bob.matches(&metadata));
+    }
+}
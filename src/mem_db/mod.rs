@@ -1,25 +1,51 @@
+mod embedder;
+mod index;
 mod log;
+mod metadata;
+mod retrieval;
+mod settings;
+mod store;
+mod summary;
 mod vector;
+mod working;
 
+#[cfg(feature = "rust-bert")]
 use rust_bert::RustBertError;
 use thiserror::Error;
 use tokio::task::JoinError;
 
+pub use self::embedder::*;
+pub use self::index::*;
 use self::log::MessageLog;
+pub use self::metadata::*;
+pub use self::retrieval::*;
+pub use self::settings::*;
+pub use self::store::*;
+use self::summary::ConversationSummary;
 use self::vector::VectorDB;
-use crate::llm::CompletionSettings;
-use crate::prompt::ChatMessage;
+pub use self::working::*;
+use crate::llm::{BackendSettings, CompletionSettings, LLMError};
+use crate::prompt::{ChatMessage, JinjaChatTemplate, SystemMessageSeverity};
 
 pub struct MemoryDB {
     log: MessageLog,
+    summary: ConversationSummary,
+    working: WorkingMemory,
     vector: VectorDB,
 }
 
 impl MemoryDB {
-    pub async fn new() -> Result<Self, MemoryDBError> {
+    /// Loads the memory. The backend is used by an embedder which sends
+    /// its requests to the LLM server.
+    pub async fn new(
+        settings: &MemorySettings,
+        backend: &BackendSettings,
+    ) -> Result<Self, MemoryDBError> {
         Ok(Self {
             log: MessageLog::new(),
-            vector: VectorDB::new().await?,
+            summary: ConversationSummary::default(),
+            working: WorkingMemory::new(&settings.working_memory),
+            vector: VectorDB::new(settings, backend).await?,
         })
     }
 
@@ -27,42 +53,204 @@ impl MemoryDB {
         self.log.update_pre_prompt(pre_prompt, tokens);
     }
 
-    pub fn add_vector_memory(&mut self, message: &ChatMessage) -> Result<(), MemoryDBError> {
-        let content = format!("{}:\n{}", message.get_role(), message.get_content());
-        self.vector.add_memory(&content)
+    pub fn working_memory(&self) -> &WorkingMemory {
+        &self.working
     }
 
-    pub fn search_vector_memory(
+    pub fn working_memory_mut(&mut self) -> &mut WorkingMemory {
+        &mut self.working
+    }
+
+    /// Archives a message to the vector memory. `importance` is from 0 to 1.
+    pub async fn add_vector_memory(
+        &mut self,
+        message: &ChatMessage,
+        importance: f32,
+    ) -> Result<(), MemoryDBError> {
+        self.add_vector_memories([(message, importance)]).await
+    }
+
+    /// Archives messages to the vector memory, each with its importance from
+    /// 0 to 1. None are archived unless all of them can be embedded.
+    pub async fn add_vector_memories<'a>(
+        &mut self,
+        messages: impl IntoIterator<Item = (&'a ChatMessage, f32)>,
+    ) -> Result<(), MemoryDBError> {
+        let memories = messages
+            .into_iter()
+            .map(|(message, importance)| {
+                let content = format!("{}:\n{}", message.get_role(), message.get_content());
+                let (source, speaker, action) = match message {
+                    ChatMessage::System { .. } => (None, None, None),
+                    ChatMessage::User {
+                        username, channel, ..
+                    } => (channel.clone(), Some(username.clone()), None),
+                    ChatMessage::Assistant { action, .. } => {
+                        (None, None, Some(action.name().to_string()))
+                    }
+                };
+
+                let metadata = MemoryMetadata {
+                    source,
+                    speaker,
+                    action,
+                    importance,
+                    ..Default::default()
+                };
+                (content, metadata)
+            })
+            .collect();
+
+        self.vector.add_memories(memories).await
+    }
+
+    /// Recalls the `count` best memories matching the filter whose
+    /// similarity to the query is at least `min_similarity`, from 0 to 1.
+    pub async fn search_vector_memory(
         &self,
         query: &str,
         count: usize,
+        min_similarity: f32,
+        filter: &MemoryFilter,
+        weights: &RetrievalWeights,
     ) -> Result<Vec<RecalledMemory>, MemoryDBError> {
-        self.vector.search(query, count)
+        self.vector
+            .search(query, count, min_similarity, filter, weights)
+            .await
+    }
+
+    /// Marks the memories as accessed now, which resets their recency. Only
+    /// the memories actually shown to the agent should be marked.
+    pub fn mark_recalled(&mut self, ids: &[i64]) -> Result<(), MemoryDBError> {
+        self.vector.mark_recalled(ids)
     }
 
     pub fn add_log_memory(&mut self, message: ChatMessage) {
         self.log.add_message(message);
     }
 
+    /// The oldest messages which have to be dropped from the log for it to
+    /// fit within `budget` tokens.
+    pub fn log_overflow(&self, budget: usize) -> &[ChatMessage] {
+        self.log.overflow(budget)
+    }
+
+    /// Drops the `count` oldest messages from the log, once they are kept
+    /// elsewhere.
+    pub fn evict_log_memory(&mut self, count: usize) {
+        self.log.evict(count);
+    }
+
+    /// The summary of the messages which have been evicted from the log.
+    pub fn get_summary(&self) -> String {
+        self.summary.text()
+    }
+
+    pub fn summary_token_count(&self) -> usize {
+        self.summary.token_count()
+    }
+
+    pub fn add_summary(&mut self, text: String, tokens: usize) {
+        self.summary.add(text, tokens);
+    }
+
+    /// Replaces the summary with a shorter summary of it.
+    pub fn condense_summary(&mut self, text: String, tokens: usize) {
+        self.summary.condense(text, tokens);
+    }
+
+    /// The system message showing the summary at the top of the log, if
+    /// anything has been summarized yet.
+    pub fn summary_message(&self) -> Option<ChatMessage> {
+        if self.summary.is_empty() {
+            return None;
+        }
+
+        Some(ChatMessage::System {
+            severity: SystemMessageSeverity::Info,
+            content: format!("Conversation so far:\n{}", self.summary.text()),
+            tokens: None,
+        })
+    }
+
+    pub fn update_log_summary(&mut self, message: ChatMessage) {
+        self.log.set_summary(message);
+    }
+
+    /// The number of tokens in the log, as counted for each message.
+    pub fn log_token_count(&self) -> usize {
+        self.log.token_count()
+    }
+
+    pub fn log_messages(&self) -> &[ChatMessage] {
+        self.log.messages()
+    }
+
+    pub fn log_messages_mut(&mut self) -> &mut [ChatMessage] {
+        self.log.messages_mut()
+    }
+
+    /// The newest messages in the log, at most `count`.
+    pub fn recent_log_memory(&self, count: usize) -> &[ChatMessage] {
+        self.log.recent(count)
+    }
+
+    /// Sets the system message of memories recalled for the next response.
+    pub fn set_recalled_memories(&mut self, message: Option<ChatMessage>) {
+        self.log.set_recalled(message);
+    }
+
     pub fn get_log_prompt(&self, settings: &CompletionSettings) -> String {
         self.log.format(settings)
     }
+
+    pub fn render_log_prompt(
+        &self,
+        template: &JinjaChatTemplate,
+    ) -> Result<String, minijinja::Error> {
+        self.log.render(template)
+    }
 }
 
 #[derive(Debug)]
 pub struct RecalledMemory {
+    /// Identifies the memory to [`MemoryDB::mark_recalled`].
+    pub id: i64,
     pub text: String,
-    pub score: f32,
+    pub score: MemoryScore,
+    pub metadata: MemoryMetadata,
 }
 
 #[derive(Debug, Error)]
 pub enum MemoryDBError {
+    #[cfg(feature = "rust-bert")]
     #[error("Failed to create model: {0}")]
     FailedToCreateModel(#[from] RustBertError),
+    #[error("The {0} embedder is not enabled in this build")]
+    EmbedderUnavailable(&'static str),
+    #[error("Failed to request an embedding: {0}")]
+    EmbeddingRequestFailed(#[from] LLMError),
     #[error("Wrong embedding size: expected: {expected}, actual: {actual}")]
     WrongEmbeddingSize { expected: usize, actual: usize },
+    #[error("Unsupported distance metric for the {index:?} index: {metric:?}")]
+    UnsupportedMetric {
+        index: VectorIndexType,
+        metric: DistanceMetric,
+    },
     #[error("Failed to search KD Tree: {0}")]
     KdTreeError(#[from] kdtree::ErrorKind),
     #[error("Failed to spawn blocking task: {0}")]
     AsyncError(#[from] JoinError),
+    #[error("Failed to access the memory store: {0}")]
+    StorageError(#[from] rusqlite::Error),
+    #[error("The memory store was written by embedding model {actual}, but {expected} is in use")]
+    EmbeddingModelMismatch { expected: String, actual: String },
+    #[error("There is no memory slot named `{0}`")]
+    UnknownMemorySlot(String),
+    #[error("Memory slot `{slot}` would hold {length} characters, but its limit is {max_chars}")]
+    MemorySlotFull {
+        slot: String,
+        length: usize,
+        max_chars: usize,
+    },
 }
@@ -0,0 +1,164 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::MemoryMetadata;
+
+/// How memories are ranked when they are recalled, following Generative
+/// Agents: a weighted sum of how relevant a memory is to the query, how
+/// recently it was accessed, and how important it is, each normalized
+/// across the candidates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetrievalWeights {
+    pub relevance: f32,
+    pub recency: f32,
+    pub importance: f32,
+    /// How much of its recency a memory keeps for every hour it is not
+    /// accessed.
+    pub recency_decay: f32,
+}
+
+impl Default for RetrievalWeights {
+    fn default() -> Self {
+        Self {
+            relevance: 1.0,
+            recency: 1.0,
+            importance: 1.0,
+            recency_decay: 0.995,
+        }
+    }
+}
+
+/// The components of a recalled memory's score, each from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryScore {
+    /// How similar the memory is to the query, which is its relevance.
+    pub similarity: f32,
+    pub recency: f32,
+    pub importance: f32,
+    /// The weighted sum of the normalized components, by which memories are
+    /// ranked.
+    pub total: f32,
+}
+
+impl RetrievalWeights {
+    /// Scores the candidates for a query, given each one's `similarity` to
+    /// the query from 0 to 1.
+    ///
+    /// Before they are weighted, the components are min-max normalized
+    /// across the candidates, so that one which barely varies, such as the
+    /// similarity of memories which are all near the query, does not decide
+    /// the ranking. A component equal for every candidate counts as 0.5.
+    pub fn score(
+        &self,
+        candidates: &[(f32, &MemoryMetadata)],
+        now: DateTime<Utc>,
+    ) -> Vec<MemoryScore> {
+        let mut scores = candidates
+            .iter()
+            .map(|&(similarity, metadata)| {
+                let hours = (now - metadata.last_accessed_at).num_seconds().max(0) as f32 / 3600.0;
+
+                MemoryScore {
+                    similarity,
+                    recency: self.recency_decay.powf(hours),
+                    importance: metadata.importance,
+                    total: 0.0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let similarity = Range::of(scores.iter().map(|score| score.similarity));
+        let recency = Range::of(scores.iter().map(|score| score.recency));
+        let importance = Range::of(scores.iter().map(|score| score.importance));
+
+        for score in &mut scores {
+            score.total = self.relevance * similarity.normalize(score.similarity)
+                + self.recency * recency.normalize(score.recency)
+                + self.importance * importance.normalize(score.importance);
+        }
+
+        scores
+    }
+}
+
+/// The least and greatest of a component across the candidates.
+struct Range {
+    min: f32,
+    max: f32,
+}
+
+impl Range {
+    fn of(values: impl Iterator<Item = f32>) -> Self {
+        values.fold(
+            Self {
+                min: f32::INFINITY,
+                max: f32::NEG_INFINITY,
+            },
+            |range, value| Self {
+                min: range.min.min(value),
+                max: range.max.max(value),
+            },
+        )
+    }
+
+    /// Scales a value in the range to 0 to 1.
+    fn normalize(&self, value: f32) -> f32 {
+        if self.max > self.min {
+            (value - self.min) / (self.max - self.min)
+        } else {
+            0.5
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn blend_scores() {
+        let now = Utc::now();
+        let weights = RetrievalWeights {
+            importance: 2.0,
+            recency_decay: 0.5,
+            ..Default::default()
+        };
+
+        let old = MemoryMetadata {
+            last_accessed_at: now - Duration::hours(2),
+            importance: 0.8,
+            ..Default::default()
+        };
+        let new = MemoryMetadata {
+            last_accessed_at: now,
+            importance: 0.2,
+            ..Default::default()
+        };
+
+        let scores = weights.score(&[(0.5, &old), (0.9, &new), (0.7, &old)], now);
+        assert_eq!(scores[0].similarity, 0.5);
+        assert_eq!(scores[0].recency, 0.25);
+        assert_eq!(scores[0].importance, 0.8);
+
+        // The similarities only differ by 0.4, but are spread from 0 to 1.
+        assert!((scores[0].total - 2.0).abs() < 1e-6);
+        assert!((scores[1].total - 2.0).abs() < 1e-6);
+        assert!((scores[2].total - 2.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn equal_components_count_half() {
+        let now = Utc::now();
+        let metadata = MemoryMetadata {
+            last_accessed_at: now,
+            importance: 0.3,
+            ..Default::default()
+        };
+
+        let scores = RetrievalWeights::default().score(&[(0.8, &metadata)], now);
+        assert!((scores[0].total - 1.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::{DistanceMetric, EmbedderSettings, RetrievalWeights, VectorIndexType};
+
+/// Configures how the agent remembers messages beyond its context window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemorySettings {
+    /// How many tokens the summary of evicted messages may use before it is
+    /// condensed. When `None`, evicted messages are only archived to the
+    /// vector memory.
+    pub summary_tokens: Option<usize>,
+
+    /// An SQLite database the vector memory is saved to and loaded from.
+    /// Without one, the vector memory is lost when the agent stops.
+    pub vector_db_file: Option<PathBuf>,
+
+    /// The model which embeds memories for the vector memory.
+    pub embedder: EmbedderSettings,
+
+    /// How the vector memory finds the memories nearest to a query.
+    pub vector_index: VectorIndexType,
+
+    /// How the distance between embeddings is measured.
+    pub vector_metric: DistanceMetric,
+
+    /// The slots of the active memory context, which the agent can edit and
+    /// which are shown in the system prompt.
+    pub working_memory: Vec<MemorySlotSettings>,
+
+    /// How many memories to recall from the vector memory before each
+    /// response. Zero disables recall.
+    pub recall_count: usize,
+
+    /// How many tokens the recalled memories may use.
+    pub recall_tokens: usize,
+
+    /// The least similarity, from 0 to 1, a memory needs to the latest
+    /// messages to be recalled.
+    pub recall_min_similarity: f32,
+
+    /// How recalled memories are ranked. Only the ten memories nearest to
+    /// the latest messages for each one recalled are ranked, so a memory
+    /// far from them is not recalled however recent or important it is.
+    pub retrieval: RetrievalWeights,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemorySlotSettings {
+    pub name: String,
+    pub max_chars: usize,
+}
+
+impl MemorySlotSettings {
+    fn new(name: &str, max_chars: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            max_chars,
+        }
+    }
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        Self {
+            summary_tokens: Some(256),
+            vector_db_file: None,
+            embedder: EmbedderSettings::default(),
+            vector_index: VectorIndexType::default(),
+            vector_metric: DistanceMetric::default(),
+            working_memory: vec![
+                MemorySlotSettings::new("persona_notes", 500),
+                MemorySlotSettings::new("user_facts", 500),
+                MemorySlotSettings::new("current_goal", 200),
+            ],
+            recall_count: 3,
+            recall_tokens: 256,
+            recall_min_similarity: 0.75,
+            retrieval: RetrievalWeights::default(),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::{MemoryDBError, MemoryMetadata};
+
+/// A memory as it is saved on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredMemory {
+    pub id: i64,
+    pub text: String,
+    pub embedding: Vec<f32>,
+    pub metadata: MemoryMetadata,
+}
+
+/// Saves the vector memory to an SQLite database, so it survives restarts.
+///
+/// The database records which embedding model it was written with, and
+/// refuses to be opened with another, as the embeddings would not be
+/// comparable.
+pub struct MemoryStore {
+    connection: Connection,
+}
+
+impl MemoryStore {
+    /// Opens the database at `path`, creating it if needed. Without a path,
+    /// the database is kept in memory only.
+    pub fn open(path: Option<&Path>, model: &str, dimension: usize) -> Result<Self, MemoryDBError> {
+        let connection = match path {
+            Some(path) => Connection::open(path)?,
+            None => Connection::open_in_memory()?,
+        };
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS memories (
+                id INTEGER PRIMARY KEY,
+                text TEXT NOT NULL,
+                embedding BLOB NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_accessed_at INTEGER NOT NULL,
+                source TEXT,
+                speaker TEXT,
+                action TEXT,
+                importance REAL NOT NULL
+            );",
+        )?;
+
+        let store = Self { connection };
+        store.check_model(model, dimension)?;
+
+        Ok(store)
+    }
+
+    fn check_model(&self, model: &str, dimension: usize) -> Result<(), MemoryDBError> {
+        let get = |key: &str| {
+            self.connection
+                .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()
+        };
+
+        match (get("model")?, get("dimension")?) {
+            (Some(stored_model), Some(stored_dimension)) => {
+                if stored_model != model || stored_dimension != dimension.to_string() {
+                    return Err(MemoryDBError::EmbeddingModelMismatch {
+                        expected: format!("{} ({})", model, dimension),
+                        actual: format!("{} ({})", stored_model, stored_dimension),
+                    });
+                }
+            }
+            _ => {
+                let mut insert = self
+                    .connection
+                    .prepare("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)")?;
+                insert.execute(["model", model])?;
+                insert.execute(["dimension", &dimension.to_string()])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn insert(
+        &self,
+        text: &str,
+        embedding: &[f32],
+        metadata: MemoryMetadata,
+    ) -> Result<StoredMemory, MemoryDBError> {
+        let bytes = embedding
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<u8>>();
+
+        self.connection.execute(
+            "INSERT INTO memories (
+                text, embedding, created_at, last_accessed_at, source, speaker, action, importance
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                text,
+                bytes,
+                metadata.created_at.timestamp(),
+                metadata.last_accessed_at.timestamp(),
+                metadata.source,
+                metadata.speaker,
+                metadata.action,
+                metadata.importance,
+            ],
+        )?;
+
+        Ok(StoredMemory {
+            id: self.connection.last_insert_rowid(),
+            text: text.to_string(),
+            embedding: embedding.to_vec(),
+            metadata,
+        })
+    }
+
+    /// Records that a memory was recalled.
+    pub fn touch(&self, id: i64, time: DateTime<Utc>) -> Result<(), MemoryDBError> {
+        self.connection.execute(
+            "UPDATE memories SET last_accessed_at = ?1 WHERE id = ?2",
+            params![time.timestamp(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Reads every memory, oldest first.
+    pub fn load(&self) -> Result<Vec<StoredMemory>, MemoryDBError> {
+        let mut query = self.connection.prepare(
+            "SELECT id, text, embedding, created_at, last_accessed_at, source, speaker, action, importance
+            FROM memories ORDER BY id",
+        )?;
+
+        let rows = query.query_map([], read_memory)?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+fn read_memory(row: &Row) -> rusqlite::Result<StoredMemory> {
+    let bytes: Vec<u8> = row.get(2)?;
+    let time = |index| {
+        row.get::<_, i64>(index)
+            .map(|secs| DateTime::from_timestamp(secs, 0).unwrap_or_default())
+    };
+
+    let created_at = time(3)?;
+    let last_accessed_at = match time(4)? {
+        // Memories from before access times were recorded.
+        time if time.timestamp() == 0 => created_at,
+        time => time,
+    };
+
+    Ok(StoredMemory {
+        id: row.get(0)?,
+        text: row.get(1)?,
+        embedding: bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect(),
+        metadata: MemoryMetadata {
+            created_at,
+            last_accessed_at,
+            source: row.get(5)?,
+            speaker: row.get(6)?,
+            action: row.get(7)?,
+            importance: row.get(8)?,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reload_memories() {
+        let path = std::env::temp_dir().join(format!("lily-store-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = MemoryStore::open(Some(&path), "model", 3).unwrap();
+        let sky = store
+            .insert(
+                "The sky is blue.",
+                &[0.5, -1.0, 2.0],
+                MemoryMetadata {
+                    speaker: Some(String::from("alice")),
+                    importance: 0.75,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        store
+            .insert(
+                "I like apples.",
+                &[1.0, 0.0, 0.25],
+                MemoryMetadata::default(),
+            )
+            .unwrap();
+
+        let accessed = DateTime::from_timestamp(2_000_000_000, 0).unwrap();
+        store.touch(sky.id, accessed).unwrap();
+        drop(store);
+
+        let store = MemoryStore::open(Some(&path), "model", 3).unwrap();
+        let memories = store.load().unwrap();
+        assert_eq!(memories.len(), 2);
+        assert_eq!(memories[0].text, "The sky is blue.");
+        assert_eq!(memories[0].embedding, vec![0.5, -1.0, 2.0]);
+        assert_eq!(memories[0].metadata.speaker.as_deref(), Some("alice"));
+        assert_eq!(memories[0].metadata.importance, 0.75);
+        assert_eq!(memories[0].metadata.last_accessed_at, accessed);
+        drop(store);
+
+        assert!(matches!(
+            MemoryStore::open(Some(&path), "model", 4),
+            Err(MemoryDBError::EmbeddingModelMismatch { .. })
+        ));
+        assert!(matches!(
+            MemoryStore::open(Some(&path), "other", 3),
+            Err(MemoryDBError::EmbeddingModelMismatch { .. })
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
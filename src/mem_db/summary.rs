@@ -0,0 +1,37 @@
+/// A running summary of the messages which have left the context window.
+///
+/// Each batch of evicted messages is summarized into a new part. When the
+/// parts grow past their limit, they are condensed into a single part, so
+/// older events end up summarized more coarsely than recent ones.
+#[derive(Debug, Default)]
+pub struct ConversationSummary {
+    /// The parts of the summary with their token counts, oldest first.
+    parts: Vec<(String, usize)>,
+}
+
+impl ConversationSummary {
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.parts.iter().map(|(_, tokens)| tokens).sum()
+    }
+
+    pub fn text(&self) -> String {
+        self.parts
+            .iter()
+            .map(|(text, _)| text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn add(&mut self, text: String, tokens: usize) {
+        self.parts.push((text, tokens));
+    }
+
+    /// Replaces every part with a condensed summary of them.
+    pub fn condense(&mut self, text: String, tokens: usize) {
+        self.parts = vec![(text, tokens)];
+    }
+}
@@ -1,85 +1,274 @@
-use kdtree::distance::squared_euclidean;
-use kdtree::KdTree;
-use rust_bert::pipelines::sentence_embeddings::{
-    SentenceEmbeddingsBuilder,
-    SentenceEmbeddingsModel,
-    SentenceEmbeddingsModelType,
-};
-use tch::Device;
+use chrono::Utc;
 
-use super::{MemoryDBError, RecalledMemory};
+use super::{
+    DistanceMetric,
+    Embedder,
+    MemoryDBError,
+    MemoryFilter,
+    MemoryMetadata,
+    MemorySettings,
+    MemoryStore,
+    RecalledMemory,
+    RetrievalWeights,
+    StoredMemory,
+    VectorIndex,
+};
+use crate::llm::BackendSettings;
 
-pub const EMBEDDING_DIM: usize = 384;
+/// How many of the nearest memories are ranked for each memory recalled.
+/// Memories outside this window are never recalled, however recent or
+/// important they are.
+const CANDIDATES_PER_RESULT: usize = 10;
 
 pub struct VectorDB {
-    model: SentenceEmbeddingsModel,
-    tree: KdTree<f32, String, [f32; EMBEDDING_DIM]>,
+    embedder: Box<dyn Embedder>,
+    /// Finds memories by their index in `memories`.
+    index: Box<dyn VectorIndex>,
+    metric: DistanceMetric,
+    memories: Vec<StoredMemory>,
+    store: MemoryStore,
 }
 
 impl VectorDB {
-    pub async fn new() -> Result<Self, MemoryDBError> {
-        let model = tokio::task::spawn_blocking(|| {
-            SentenceEmbeddingsBuilder::remote(SentenceEmbeddingsModelType::AllMiniLmL12V2)
-                .with_device(Device::Cpu)
-                .create_model()
-        })
-        .await??;
+    /// Loads the embedding model, and the memories saved to the database
+    /// file. Without a file, memories are kept until the agent stops.
+    pub async fn new(
+        settings: &MemorySettings,
+        backend: &BackendSettings,
+    ) -> Result<Self, MemoryDBError> {
+        let embedder = settings.embedder.build(backend).await?;
+        let dimension = embedder.dimension();
+
+        let path = settings.vector_db_file.as_deref();
+        let store = MemoryStore::open(path, embedder.model_name(), dimension)?;
+        let memories = store.load()?;
+        let metric = settings.vector_metric;
+        let mut index = settings.vector_index.build(dimension, metric)?;
+
+        for (i, memory) in memories.iter().enumerate() {
+            index.insert(i, &memory.embedding)?;
+        }
 
         Ok(Self {
-            model,
-            tree: KdTree::new(EMBEDDING_DIM),
+            embedder,
+            index,
+            metric,
+            memories,
+            store,
         })
     }
 
-    fn embed(&self, text: &str) -> Result<[f32; EMBEDDING_DIM], MemoryDBError> {
-        let embeddings = self.model.encode(&[text])?;
-        let tensor = embeddings[0].as_slice();
-        let embed: [f32; EMBEDDING_DIM] =
-            tensor
-                .try_into()
-                .map_err(|_| MemoryDBError::WrongEmbeddingSize {
-                    expected: EMBEDDING_DIM,
-                    actual: tensor.len(),
-                })?;
-
-        Ok(embed)
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, MemoryDBError> {
+        let embedding = self.embedder.embed(text).await?;
+        let expected = self.embedder.dimension();
+
+        match embedding.len() {
+            actual if actual == expected => Ok(embedding),
+            actual => Err(MemoryDBError::WrongEmbeddingSize { expected, actual }),
+        }
     }
 
-    pub fn add_memory(&mut self, text: &str) -> Result<(), MemoryDBError> {
-        let embedding = self.embed(text)?;
-        self.tree.add(embedding, text.to_owned())?;
+    /// Embeds and saves the memories. Every memory is embedded before any is
+    /// saved, so none are saved when an embedding fails.
+    pub async fn add_memories(
+        &mut self,
+        memories: Vec<(String, MemoryMetadata)>,
+    ) -> Result<(), MemoryDBError> {
+        let mut embeddings = Vec::with_capacity(memories.len());
+        for (text, _) in &memories {
+            embeddings.push(self.embed(text).await?);
+        }
+
+        for ((text, metadata), embedding) in memories.into_iter().zip(embeddings) {
+            let memory = self.store.insert(&text, &embedding, metadata)?;
+            self.index.insert(self.memories.len(), &embedding)?;
+            self.memories.push(memory);
+        }
+
         Ok(())
     }
 
-    pub fn search(&self, query: &str, count: usize) -> Result<Vec<RecalledMemory>, MemoryDBError> {
-        let query_embedding = self.embed(query)?;
-        let nearest = self
-            .tree
-            .nearest(&query_embedding, count, &squared_euclidean)?;
+    /// Finds the `count` best memories matching the filter, ranked by the
+    /// weights among the memories nearest to the query. Memories less
+    /// similar to the query than `min_similarity` are left out.
+    pub async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        min_similarity: f32,
+        filter: &MemoryFilter,
+        weights: &RetrievalWeights,
+    ) -> Result<Vec<RecalledMemory>, MemoryDBError> {
+        if self.index.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        Ok(nearest
-            .iter()
-            .map(|(distance, data)| RecalledMemory {
-                text: data.to_string(),
-                score: distance.sqrt(),
+        let query_embedding = self.embed(query).await?;
+        let (similarities, indices): (Vec<_>, Vec<_>) = self
+            .index
+            .search(&query_embedding, count * CANDIDATES_PER_RESULT, &|i| {
+                filter.matches(&self.memories[i].metadata)
+            })
+            .into_iter()
+            .map(|(i, distance)| {
+                (
+                    (self.metric.similarity(distance), &self.memories[i].metadata),
+                    i,
+                )
             })
-            .collect())
+            .filter(|((similarity, _), _)| *similarity >= min_similarity)
+            .unzip();
+
+        let scores = weights.score(&similarities, Utc::now());
+        let mut candidates = scores.into_iter().zip(indices).collect::<Vec<_>>();
+
+        candidates.sort_by(|(a, _), (b, _)| b.total.total_cmp(&a.total));
+        candidates.truncate(count);
+
+        let recalled = candidates
+            .into_iter()
+            .map(|(score, index)| {
+                let memory = &self.memories[index];
+                RecalledMemory {
+                    id: memory.id,
+                    text: memory.text.clone(),
+                    score,
+                    metadata: memory.metadata.clone(),
+                }
+            })
+            .collect();
+
+        Ok(recalled)
+    }
+
+    /// Marks the memories with the given ids as accessed now.
+    pub fn mark_recalled(&mut self, ids: &[i64]) -> Result<(), MemoryDBError> {
+        let now = Utc::now();
+        for memory in &mut self.memories {
+            if ids.contains(&memory.id) {
+                self.store.touch(memory.id, now)?;
+                memory.metadata.last_accessed_at = now;
+            }
+        }
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use chrono::Duration;
+
     use super::*;
+    use crate::mem_db::{EmbedderSettings, VectorIndexType};
 
+    const TEXTS: [&str; 3] = [
+        "My favorite color is red.",
+        "I like apples.",
+        "The sky is blue.",
+    ];
+
+    fn relevance_only() -> RetrievalWeights {
+        RetrievalWeights {
+            recency: 0.0,
+            importance: 0.0,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "rust-bert")]
     #[tokio::test]
     async fn simple_db() {
-        let mut db = VectorDB::new().await.unwrap();
+        let settings = MemorySettings {
+            vector_index: VectorIndexType::BruteForce,
+            ..Default::default()
+        };
+        let mut db = VectorDB::new(&settings, &BackendSettings::default())
+            .await
+            .unwrap();
+
+        let memories = TEXTS.map(|text| (text.to_string(), MemoryMetadata::default()));
+        db.add_memories(memories.to_vec()).await.unwrap();
+
+        let results = db
+            .search("fruit", 1, 0.0, &MemoryFilter::default(), &relevance_only())
+            .await
+            .unwrap();
+        assert_eq!(results[0].text, "I like apples.");
+    }
 
-        db.add_memory("My favorite color is red.").unwrap();
-        db.add_memory("I like apples.").unwrap();
-        db.add_memory("The sky is blue.").unwrap();
+    #[tokio::test]
+    async fn cut_off_dissimilar_memories() {
+        let settings = MemorySettings {
+            embedder: EmbedderSettings::Hashing { dimension: 256 },
+            vector_index: VectorIndexType::BruteForce,
+            ..Default::default()
+        };
+        let mut db = VectorDB::new(&settings, &BackendSettings::default())
+            .await
+            .unwrap();
 
-        let results = db.search("fruit", 1).unwrap();
+        let memories = TEXTS.map(|text| (text.to_string(), MemoryMetadata::default()));
+        db.add_memories(memories.to_vec()).await.unwrap();
+
+        // Memories sharing no words with the query have a similarity of 0.5.
+        let results = db
+            .search(
+                "apples",
+                3,
+                0.6,
+                &MemoryFilter::default(),
+                &relevance_only(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
         assert_eq!(results[0].text, "I like apples.");
+        assert!(results[0].score.similarity > 0.6);
+    }
+
+    #[tokio::test]
+    async fn only_mark_recalled_memories() {
+        let settings = MemorySettings {
+            embedder: EmbedderSettings::Hashing { dimension: 256 },
+            vector_index: VectorIndexType::BruteForce,
+            ..Default::default()
+        };
+        let mut db = VectorDB::new(&settings, &BackendSettings::default())
+            .await
+            .unwrap();
+
+        let created = Utc::now() - Duration::hours(1);
+        let memories = TEXTS.map(|text| {
+            let metadata = MemoryMetadata {
+                created_at: created,
+                last_accessed_at: created,
+                ..Default::default()
+            };
+            (text.to_string(), metadata)
+        });
+        db.add_memories(memories.to_vec()).await.unwrap();
+
+        let filter = MemoryFilter::default();
+        let weights = relevance_only();
+
+        // Searching alone leaves every memory as it was.
+        let results = db.search("is", 3, 0.0, &filter, &weights).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results
+            .iter()
+            .all(|memory| memory.metadata.last_accessed_at == created));
+
+        let sky = results
+            .iter()
+            .find(|memory| memory.text == "The sky is blue.")
+            .unwrap();
+        db.mark_recalled(&[sky.id]).unwrap();
+
+        let results = db.search("is", 3, 0.0, &filter, &weights).await.unwrap();
+        for memory in results {
+            let marked = memory.metadata.last_accessed_at > created;
+            assert_eq!(marked, memory.text == "The sky is blue.");
+        }
     }
 }
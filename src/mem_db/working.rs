@@ -0,0 +1,143 @@
+use super::{MemoryDBError, MemorySlotSettings};
+
+/// The agent's active memory context: named slots of text which it can edit,
+/// shown in the system prompt.
+#[derive(Debug, Clone)]
+pub struct WorkingMemory {
+    slots: Vec<MemorySlot>,
+}
+
+#[derive(Debug, Clone)]
+struct MemorySlot {
+    name: String,
+    max_chars: usize,
+    content: String,
+}
+
+impl WorkingMemory {
+    pub fn new(slots: &[MemorySlotSettings]) -> Self {
+        Self {
+            slots: slots
+                .iter()
+                .map(|slot| MemorySlot {
+                    name: slot.name.clone(),
+                    max_chars: slot.max_chars,
+                    content: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn slot_names(&self) -> impl Iterator<Item = &str> {
+        self.slots.iter().map(|slot| slot.name.as_str())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.slots
+            .iter()
+            .find(|slot| slot.name == name)
+            .map(|slot| slot.content.as_str())
+    }
+
+    fn slot_mut(&mut self, name: &str) -> Result<&mut MemorySlot, MemoryDBError> {
+        self.slots
+            .iter_mut()
+            .find(|slot| slot.name == name)
+            .ok_or_else(|| MemoryDBError::UnknownMemorySlot(name.to_string()))
+    }
+
+    /// Adds a line to the end of a slot.
+    pub fn append(&mut self, name: &str, text: &str) -> Result<(), MemoryDBError> {
+        let slot = self.slot_mut(name)?;
+        let content = match slot.content.is_empty() {
+            true => text.to_string(),
+            false => format!("{}\n{}", slot.content, text),
+        };
+        slot.set(content)
+    }
+
+    pub fn replace(&mut self, name: &str, text: &str) -> Result<(), MemoryDBError> {
+        self.slot_mut(name)?.set(text.to_string())
+    }
+
+    pub fn clear(&mut self, name: &str) -> Result<(), MemoryDBError> {
+        self.slot_mut(name)?.content.clear();
+        Ok(())
+    }
+
+    /// Formats every slot for the system prompt.
+    pub fn render(&self) -> String {
+        if self.slots.is_empty() {
+            return String::from("EMPTY");
+        }
+
+        self.slots
+            .iter()
+            .map(|slot| {
+                let content = match slot.content.is_empty() {
+                    true => "EMPTY",
+                    false => slot.content.as_str(),
+                };
+
+                format!(
+                    "## {} ({}/{} characters)\n{}",
+                    slot.name,
+                    slot.content.chars().count(),
+                    slot.max_chars,
+                    content
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+impl MemorySlot {
+    fn set(&mut self, content: String) -> Result<(), MemoryDBError> {
+        let length = content.chars().count();
+        if length > self.max_chars {
+            return Err(MemoryDBError::MemorySlotFull {
+                slot: self.name.clone(),
+                length,
+                max_chars: self.max_chars,
+            });
+        }
+
+        self.content = content;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edit_slots() {
+        let mut memory = WorkingMemory::new(&[MemorySlotSettings {
+            name: String::from("goal"),
+            max_chars: 10,
+        }]);
+
+        memory.append("goal", "Learn").unwrap();
+        memory.append("goal", "Grow").unwrap();
+        assert_eq!(memory.get("goal"), Some("Learn\nGrow"));
+        assert_eq!(memory.render(), "## goal (10/10 characters)\nLearn\nGrow");
+
+        assert!(matches!(
+            memory.append("goal", "More"),
+            Err(MemoryDBError::MemorySlotFull { length: 15, .. })
+        ));
+        assert_eq!(memory.get("goal"), Some("Learn\nGrow"));
+
+        memory.replace("goal", "Rest").unwrap();
+        assert_eq!(memory.get("goal"), Some("Rest"));
+        memory.clear("goal").unwrap();
+        assert_eq!(memory.get("goal"), Some(""));
+
+        assert!(matches!(
+            memory.clear("mood"),
+            Err(MemoryDBError::UnknownMemorySlot(_))
+        ));
+    }
+}
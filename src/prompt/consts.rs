@@ -21,6 +21,10 @@ messages to the user unless you are in the SAY state, otherwise it will not be s
 These states are:
 {action_states}
 
+# Commands
+Arguments containing spaces may be wrapped in quotes. The available commands are:
+{command_list}
+
 # Personality
 {personality}
 
@@ -33,3 +37,13 @@ These states are:
 pub const ACTION_STATE: &str = r#"
 - {name}:
     - {explanation}"#;
+
+pub const SUMMARY_PROMPT: &str = r#"
+You maintain the long-running memory of {ai_name}, an AI. Summarize the text given to you in a few sentences, written in the third person.
+Keep names, facts, decisions, goals, feelings and anything {ai_name} promised to do. Leave out greetings and small talk, and never add anything which did not happen.
+Respond with the summary only."#;
+
+pub const IMPORTANCE_PROMPT: &str = r#"
+You maintain the long-running memory of {ai_name}, an AI. On a scale of 1 to 10, rate how important the message given to you is for {ai_name} to remember,
+where 1 is purely mundane, such as a greeting or small talk, and 10 is extremely important, such as a personal fact, a goal, a promise or a major event.
+Respond with the number only."#;
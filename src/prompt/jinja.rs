@@ -0,0 +1,201 @@
+use chrono::Local;
+use minijinja::{context, Environment, Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+
+use super::ChatMessage;
+
+/// The name the template is compiled under in its environment.
+const TEMPLATE_NAME: &str = "chat_template";
+
+/// A Jinja chat template as embedded in a model's `tokenizer.chat_template`
+/// metadata, together with the special tokens it refers to.
+///
+/// The template is compiled once, when it is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "TemplateSource", into = "TemplateSource")]
+pub struct JinjaChatTemplate {
+    source: String,
+    pub bos_token: String,
+    pub eos_token: String,
+    env: Environment<'static>,
+}
+
+/// How a [`JinjaChatTemplate`] is serialized.
+#[derive(Serialize, Deserialize)]
+struct TemplateSource {
+    source: String,
+    #[serde(default)]
+    bos_token: String,
+    #[serde(default)]
+    eos_token: String,
+}
+
+impl TryFrom<TemplateSource> for JinjaChatTemplate {
+    type Error = Error;
+
+    fn try_from(template: TemplateSource) -> Result<Self, Error> {
+        Ok(JinjaChatTemplate::new(template.source)?
+            .with_special_tokens(template.bos_token, template.eos_token))
+    }
+}
+
+impl From<JinjaChatTemplate> for TemplateSource {
+    fn from(template: JinjaChatTemplate) -> Self {
+        Self {
+            source: template.source,
+            bos_token: template.bos_token,
+            eos_token: template.eos_token,
+        }
+    }
+}
+
+impl PartialEq for JinjaChatTemplate {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+            && self.bos_token == other.bos_token
+            && self.eos_token == other.eos_token
+    }
+}
+
+#[derive(Serialize)]
+struct TemplateMessage {
+    role: &'static str,
+    content: String,
+}
+
+impl JinjaChatTemplate {
+    /// Compiles a template, failing if it has a syntax error.
+    pub fn new(source: impl Into<String>) -> Result<Self, Error> {
+        let source = source.into();
+
+        let mut env = Environment::new();
+        env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+        env.add_function("raise_exception", |message: String| -> Result<(), Error> {
+            Err(Error::new(ErrorKind::InvalidOperation, message))
+        });
+        env.add_function("strftime_now", |format: String| {
+            Local::now().format(&format).to_string()
+        });
+        env.add_template_owned(TEMPLATE_NAME, source.clone())?;
+
+        Ok(Self {
+            source,
+            bos_token: String::new(),
+            eos_token: String::new(),
+            env,
+        })
+    }
+
+    pub fn with_special_tokens(
+        mut self,
+        bos_token: impl Into<String>,
+        eos_token: impl Into<String>,
+    ) -> Self {
+        self.bos_token = bos_token.into();
+        self.eos_token = eos_token.into();
+        self
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Renders the messages into a prompt. With `add_generation_prompt`, the
+    /// prompt ends with an open assistant turn for the model to complete.
+    ///
+    /// The messages are first shaped into turns, see [`to_turns`].
+    pub fn render(
+        &self,
+        messages: &[ChatMessage],
+        add_generation_prompt: bool,
+    ) -> Result<String, Error> {
+        let messages = to_turns(messages);
+
+        self.env.get_template(TEMPLATE_NAME)?.render(context! {
+            messages => messages,
+            add_generation_prompt => add_generation_prompt,
+            bos_token => self.bos_token,
+            eos_token => self.eos_token,
+        })
+    }
+}
+
+/// Shapes the messages into the turns most chat templates insist on: a
+/// single system message first, then user and assistant turns which
+/// alternate, starting with the user.
+///
+/// System messages after the first are shown as user turns, and consecutive
+/// messages with the same role are merged into one turn. When the assistant
+/// speaks first, an empty user turn is added before it.
+fn to_turns(messages: &[ChatMessage]) -> Vec<TemplateMessage> {
+    let mut turns: Vec<TemplateMessage> = Vec::new();
+
+    for (i, message) in messages.iter().enumerate() {
+        let role = match message {
+            ChatMessage::System { .. } if i == 0 => "system",
+            ChatMessage::System { .. } | ChatMessage::User { .. } => "user",
+            ChatMessage::Assistant { .. } => "assistant",
+        };
+
+        match turns.last_mut() {
+            Some(last) if last.role == role => {
+                last.content.push('\n');
+                last.content.push_str(&message.get_content());
+                continue;
+            }
+            Some(TemplateMessage { role: "system", .. }) | None if role == "assistant" => {
+                turns.push(TemplateMessage {
+                    role: "user",
+                    content: String::new(),
+                });
+            }
+            _ => {}
+        }
+
+        turns.push(TemplateMessage {
+            role,
+            content: message.get_content(),
+        });
+    }
+
+    turns
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actions::MessageAction;
+    use crate::prompt::SystemMessageSeverity;
+
+    #[test]
+    fn render_chatml_template() {
+        let template = JinjaChatTemplate::new(
+            "{% for message in messages %}{{ '<|im_start|>' + message['role'] + '\\n' + message['content'].strip() + '<|im_end|>' + '\\n' }}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant\\n' }}{% endif %}",
+        )
+        .unwrap();
+
+        let messages = vec![
+            ChatMessage::System {
+                severity: SystemMessageSeverity::Info,
+                content: String::from("Be nice."),
+                tokens: None,
+            },
+            ChatMessage::Assistant {
+                action: MessageAction::Say,
+                content: String::from("Hello "),
+                tokens: None,
+            },
+        ];
+
+        let prompt = template.render(&messages, true).unwrap();
+        assert_eq!(
+            prompt,
+            "<|im_start|>system\n[INFO] Be nice.<|im_end|>\n<|im_start|>user\n<|im_end|>\n<|im_start|>assistant\nSAY: Hello<|im_end|>\n<|im_start|>assistant\n"
+        );
+
+        let template =
+            JinjaChatTemplate::new("{{ raise_exception('Roles must alternate') }}").unwrap();
+        assert!(template.render(&messages, true).is_err());
+        assert!(JinjaChatTemplate::new("{% if %}").is_err());
+    }
+}
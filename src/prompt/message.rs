@@ -1,4 +1,4 @@
-use super::SystemMessageSeverity;
+use super::{ChatRole, SystemMessageSeverity};
 use crate::actions::MessageAction;
 use crate::llm::CompletionSettings;
 
@@ -11,6 +11,8 @@ pub enum ChatMessage {
     },
     User {
         username: String,
+        /// The channel the message was received from, if any.
+        channel: Option<String>,
         content: String,
         tokens: Option<usize>,
     },
@@ -52,37 +54,19 @@ impl ChatMessage {
         }
     }
 
-    pub fn format(&self, settings: &CompletionSettings) -> String {
+    pub fn role(&self) -> ChatRole {
         match self {
-            ChatMessage::System { .. } => {
-                format!(
-                    "{}{}{}",
-                    settings.system_message_prefix,
-                    self.get_content(),
-                    settings.system_message_suffix
-                )
-            }
-
-            ChatMessage::User { .. } => {
-                format!(
-                    "{}{}{}",
-                    settings.user_message_prefix,
-                    self.get_content(),
-                    settings.user_message_suffix
-                )
-            }
-
-            ChatMessage::Assistant { .. } => {
-                format!(
-                    "{}{}{}",
-                    settings.assistant_message_prefix,
-                    self.get_content(),
-                    settings.assistant_message_suffix
-                )
-            }
+            ChatMessage::System { .. } => ChatRole::System,
+            ChatMessage::User { .. } => ChatRole::User,
+            ChatMessage::Assistant { .. } => ChatRole::Assistant,
         }
     }
 
+    pub fn format(&self, settings: &CompletionSettings) -> String {
+        let (prefix, suffix) = settings.chat_template.affixes(self.role(), settings);
+        format!("{}{}{}", prefix, self.get_content(), suffix)
+    }
+
     pub fn get_tokens(&self) -> Option<usize> {
         match self {
             ChatMessage::System { tokens, .. } => *tokens,
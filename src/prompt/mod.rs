@@ -1,7 +1,11 @@
 mod consts;
+mod jinja;
 mod message;
 mod severity;
+mod template;
 
 pub use consts::*;
+pub use jinja::*;
 pub use message::*;
 pub use severity::*;
+pub use template::*;
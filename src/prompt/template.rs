@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::llm::CompletionSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// The markup a model was trained to expect around each chat message.
+///
+/// `Custom` uses the `*_message_prefix` and `*_message_suffix` fields of
+/// [`CompletionSettings`] instead of a preset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTemplate {
+    #[serde(rename = "chatml")]
+    ChatMl,
+    #[serde(rename = "llama2", alias = "llama-2")]
+    Llama2,
+    #[serde(rename = "llama3", alias = "llama-3")]
+    Llama3,
+    Mistral,
+    Alpaca,
+    Vicuna,
+    Zephyr,
+    Raw,
+    #[default]
+    Custom,
+}
+
+impl ChatTemplate {
+    /// Returns the text written before and after a message with the given
+    /// role.
+    pub fn affixes<'a>(
+        &self,
+        role: ChatRole,
+        settings: &'a CompletionSettings,
+    ) -> (&'a str, &'a str) {
+        use ChatRole::*;
+
+        match (self, role) {
+            (ChatTemplate::ChatMl, System) => ("<|im_start|>system\n", "<|im_end|>\n"),
+            (ChatTemplate::ChatMl, User) => ("<|im_start|>user\n", "<|im_end|>\n"),
+            (ChatTemplate::ChatMl, Assistant) => ("<|im_start|>assistant\n", "<|im_end|>\n"),
+
+            (ChatTemplate::Llama2, System) => ("[INST] <<SYS>>\n", "\n<</SYS>> [/INST]\n"),
+            (ChatTemplate::Llama2 | ChatTemplate::Mistral, User) => ("[INST] ", " [/INST]\n"),
+            (ChatTemplate::Llama2 | ChatTemplate::Mistral, Assistant) => ("", "</s>\n"),
+
+            (ChatTemplate::Llama3, System) => (
+                "<|start_header_id|>system<|end_header_id|>\n\n",
+                "<|eot_id|>",
+            ),
+            (ChatTemplate::Llama3, User) => {
+                ("<|start_header_id|>user<|end_header_id|>\n\n", "<|eot_id|>")
+            }
+            (ChatTemplate::Llama3, Assistant) => (
+                "<|start_header_id|>assistant<|end_header_id|>\n\n",
+                "<|eot_id|>",
+            ),
+
+            // Mistral has no system role, so system messages are instructions.
+            (ChatTemplate::Mistral, System) => ("[INST] ", " [/INST]\n"),
+
+            (ChatTemplate::Alpaca, System) => ("", "\n\n"),
+            (ChatTemplate::Alpaca, User) => ("### Instruction:\n", "\n\n"),
+            (ChatTemplate::Alpaca, Assistant) => ("### Response:\n", "\n\n"),
+
+            (ChatTemplate::Vicuna, System) => ("", "\n\n"),
+            (ChatTemplate::Vicuna, User) => ("USER: ", "\n"),
+            (ChatTemplate::Vicuna, Assistant) => ("ASSISTANT: ", "</s>\n"),
+
+            (ChatTemplate::Zephyr, System) => ("<|system|>\n", "</s>\n"),
+            (ChatTemplate::Zephyr, User) => ("<|user|>\n", "</s>\n"),
+            (ChatTemplate::Zephyr, Assistant) => ("<|assistant|>\n", "</s>\n"),
+
+            (ChatTemplate::Raw, _) => ("", "\n"),
+
+            (ChatTemplate::Custom, System) => (
+                &settings.system_message_prefix,
+                &settings.system_message_suffix,
+            ),
+            (ChatTemplate::Custom, User) => {
+                (&settings.user_message_prefix, &settings.user_message_suffix)
+            }
+            (ChatTemplate::Custom, Assistant) => (
+                &settings.assistant_message_prefix,
+                &settings.assistant_message_suffix,
+            ),
+        }
+    }
+
+    /// The tokens which mark the end of a message, so generation stops
+    /// before the model starts writing the next one.
+    pub fn stop_tokens(&self) -> &'static [&'static str] {
+        match self {
+            ChatTemplate::ChatMl => &["<|im_end|>", "<|im_start|>"],
+            ChatTemplate::Llama2 | ChatTemplate::Mistral => &["</s>", "[INST]"],
+            ChatTemplate::Llama3 => &["<|eot_id|>", "<|start_header_id|>"],
+            ChatTemplate::Alpaca => &["### Instruction:", "### Response:"],
+            ChatTemplate::Vicuna => &["</s>", "USER:"],
+            ChatTemplate::Zephyr => &["</s>", "<|user|>"],
+            ChatTemplate::Raw | ChatTemplate::Custom => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actions::MessageAction;
+    use crate::prompt::ChatMessage;
+
+    #[test]
+    fn format_with_preset() {
+        let settings: CompletionSettings = serde_json::from_value(serde_json::json!({
+            "chat_template": "llama2",
+            "temperature": 0.7,
+            "top_p": 1.0,
+            "min_p": 0.05,
+            "top_k": 40,
+            "stop_tokens": ["\n", "</s>"],
+            "max_tokens": 128,
+            "repeat_penalty": 1.1,
+            "repeat_last_n": 64,
+            "frequency_penalty": 0.0,
+            "presence_penalty": 0.0,
+            "logit_bias": [],
+        }))
+        .unwrap();
+
+        let message = ChatMessage::Assistant {
+            action: MessageAction::Say,
+            content: String::from("Hello"),
+            tokens: None,
+        };
+
+        assert_eq!(settings.chat_template, ChatTemplate::Llama2);
+        assert_eq!(message.format(&settings), "SAY: Hello</s>\n");
+        assert_eq!(settings.all_stop_tokens(), vec!["\n", "</s>", "[INST]"]);
+    }
+}